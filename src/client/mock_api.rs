@@ -0,0 +1,449 @@
+//! # Mock API
+//!
+//! This crate implements functions for the mock `Client` that are not
+//! available in the `RpcApi` trait. They can be used to create scenarios that
+//! are hard to create with a real Bitcoin node.
+
+use super::{Client, CoinbaseOptions, HistoryEntry};
+use bitcoin::{address::NetworkChecked, Address, BlockHash, Network, Script, Transaction};
+use bitcoincore_rpc::json::ListUnspentResultEntry;
+
+impl Client {
+    /// Generates `block_num` amount of blocks on top of the block with
+    /// `block_hash` and sends block rewards to `address`. Given block can be
+    /// in a stale branch, which makes it possible to grow competing branches.
+    ///
+    /// Blocks that don't extend the tip of the active chain only have a
+    /// coinbase transaction. Ledger switches to the branch with the most work,
+    /// which can cause a reorganization.
+    #[tracing::instrument(skip_all)]
+    pub fn generate_to_address_on(
+        &self,
+        block_hash: &BlockHash,
+        block_num: u64,
+        address: &Address<NetworkChecked>,
+    ) -> bitcoincore_rpc::Result<Vec<BlockHash>> {
+        self.ledger.check_address(address)?;
+
+        let mut hashes: Vec<BlockHash> = Vec::new();
+        let mut prev_blockhash = *block_hash;
+
+        for _ in 0..block_num {
+            prev_blockhash = self.ledger.mine_block_on(prev_blockhash, address)?;
+            hashes.push(prev_blockhash);
+        }
+
+        Ok(hashes)
+    }
+
+    /// Returns the network of the ledger, which is set with the `network`
+    /// parameter of the `url` in `Client::new()`. Default is regtest.
+    #[tracing::instrument(skip_all)]
+    pub fn get_network(&self) -> bitcoincore_rpc::Result<Network> {
+        Ok(self.ledger.get_network()?)
+    }
+
+    /// Same as `generate_to_address`, but coinbase transactions of the blocks
+    /// are customized with `options`. Custom scriptSig data, payout script and
+    /// extra outputs can be used to test coinbase parsing code.
+    #[tracing::instrument(skip_all)]
+    pub fn generate_to_address_with_coinbase(
+        &self,
+        block_num: u64,
+        address: &Address<NetworkChecked>,
+        options: &CoinbaseOptions,
+    ) -> bitcoincore_rpc::Result<Vec<BlockHash>> {
+        self.ledger.check_address(address)?;
+
+        let mut hashes: Vec<BlockHash> = Vec::new();
+
+        for _ in 0..block_num {
+            hashes.push(self.ledger.mine_block_with_coinbase(address, options)?);
+        }
+
+        Ok(hashes)
+    }
+
+    /// Mines a block with exactly the given `transactions`, in given order, and
+    /// sends block reward to `address`. Transactions can be mempool
+    /// transactions or new ones, which are verified first. Rest of the mempool
+    /// is not mined.
+    #[tracing::instrument(skip_all)]
+    pub fn generate_block(
+        &self,
+        address: &Address<NetworkChecked>,
+        transactions: &[Transaction],
+    ) -> bitcoincore_rpc::Result<BlockHash> {
+        self.ledger.check_address(address)?;
+
+        Ok(self
+            .ledger
+            .mine_block_with_transactions(address, transactions.to_vec())?)
+    }
+
+    /// Mines a block with the mempool transactions, like
+    /// `generate_to_address`, but with the given `time` in UNIX format. Time
+    /// must be greater than the median time past of the tip and at most 2
+    /// hours ahead of the ledger's clock.
+    #[tracing::instrument(skip_all)]
+    pub fn generate_block_at(
+        &self,
+        address: &Address<NetworkChecked>,
+        time: u32,
+    ) -> bitcoincore_rpc::Result<BlockHash> {
+        self.ledger.check_address(address)?;
+
+        Ok(self.ledger.mine_block_at(address, time)?)
+    }
+
+    /// Sets the ledger's clock to `timestamp`, in UNIX format, like Bitcoin
+    /// Core's `setmocktime`. New blocks are mined with this time, unless the
+    /// median time past is later. Setting it to 0 makes the clock follow the
+    /// system time again.
+    #[tracing::instrument(skip_all)]
+    pub fn set_mock_time(&self, timestamp: u64) -> bitcoincore_rpc::Result<()> {
+        let Ok(timestamp) = u32::try_from(timestamp) else {
+            return Err(bitcoincore_rpc::Error::ReturnedError(format!(
+                "Mock time {timestamp} is out of range"
+            )));
+        };
+
+        Ok(self.ledger.set_mock_time(timestamp)?)
+    }
+
+    /// Saves current state of the ledger and returns an ID for it. Ledger can
+    /// be restored to that state, using [`Client::restore`].
+    ///
+    /// This is useful for reusing an expensive setup, like a chain with many
+    /// blocks, across different test cases.
+    #[tracing::instrument(skip_all)]
+    pub fn snapshot(&self) -> bitcoincore_rpc::Result<u32> {
+        Ok(self.ledger.snapshot()?)
+    }
+
+    /// Restores the ledger to the snapshot with `id`. Snapshot is kept, so it
+    /// can be restored more than once.
+    #[tracing::instrument(skip_all)]
+    pub fn restore(&self, id: u32) -> bitcoincore_rpc::Result<()> {
+        Ok(self.ledger.restore(id)?)
+    }
+
+    /// Creates a new `Client` named `new_name`, which starts with an exact
+    /// copy of this client's ledger. After that, both of the clients evolve
+    /// independently. `new_name` is handled the same way as the `url`
+    /// parameter of `Client::new()`.
+    #[tracing::instrument(skip(self))]
+    pub fn fork(&self, new_name: &str) -> bitcoincore_rpc::Result<Client> {
+        Ok(Client {
+            ledger: self.ledger.fork(new_name)?,
+        })
+    }
+
+    /// Returns every transaction that pays to or spends from `script`, in
+    /// block order. Mempool transactions come last and they don't have a
+    /// height.
+    #[tracing::instrument(skip_all)]
+    pub fn get_history(&self, script: &Script) -> bitcoincore_rpc::Result<Vec<HistoryEntry>> {
+        Ok(self.ledger.get_history(script)?)
+    }
+
+    /// Returns unspent outputs that are locked to `script`, in block order.
+    /// Outputs of the mempool transactions are included with 0 confirmations.
+    ///
+    /// Wallet related fields, like `label` and `descriptor`, are not set.
+    #[tracing::instrument(skip_all)]
+    pub fn get_utxos(
+        &self,
+        script: &Script,
+    ) -> bitcoincore_rpc::Result<Vec<ListUnspentResultEntry>> {
+        let next_height = self.ledger.get_block_height()? + 1;
+        let network = self.ledger.get_network()?;
+
+        let utxos = self
+            .ledger
+            .get_utxos(script)?
+            .into_iter()
+            .map(|utxo| ListUnspentResultEntry {
+                txid: utxo.outpoint.txid,
+                vout: utxo.outpoint.vout,
+                address: Address::from_script(script, network)
+                    .ok()
+                    .map(|address| address.into_unchecked()),
+                label: None,
+                redeem_script: None,
+                witness_script: None,
+                script_pub_key: utxo.txout.script_pubkey,
+                amount: utxo.txout.value,
+                confirmations: next_height.saturating_sub(utxo.height),
+                spendable: true,
+                solvable: true,
+                descriptor: None,
+                safe: true,
+            })
+            .collect();
+
+        Ok(utxos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ledger::Ledger, Client, CoinbaseOptions, HistoryEntry, RpcApiWrapper};
+    use bitcoin::{Amount, OutPoint, ScriptBuf, TxIn, TxOut};
+    use bitcoincore_rpc::{json::GetChainTipsResultStatus, RpcApi};
+
+    #[test]
+    fn generate_to_address_on() {
+        let rpc = Client::new("generate_to_address_on", bitcoincore_rpc::Auth::None).unwrap();
+        let address = Ledger::generate_credential_from_witness().address;
+
+        let hashes = rpc.generate_to_address(3, &address).unwrap();
+
+        // Stale branch with less work won't be activated.
+        let fork = rpc.generate_to_address_on(&hashes[0], 1, &address).unwrap();
+        assert_eq!(rpc.get_best_block_hash().unwrap(), hashes[2]);
+
+        let tips = rpc.get_chain_tips().unwrap();
+        assert_eq!(tips.len(), 2);
+        assert_eq!(tips[0].hash, hashes[2]);
+        assert_eq!(tips[0].status, GetChainTipsResultStatus::Active);
+        assert_eq!(tips[0].branch_length, 0);
+        assert_eq!(tips[1].hash, fork[0]);
+        assert_eq!(tips[1].status, GetChainTipsResultStatus::ValidHeaders);
+        assert_eq!(tips[1].branch_length, 1);
+
+        // Branch with more work is activated.
+        let fork = rpc.generate_to_address_on(&fork[0], 2, &address).unwrap();
+        assert_eq!(rpc.get_best_block_hash().unwrap(), fork[1]);
+        assert_eq!(rpc.get_block_count().unwrap(), 4);
+
+        let tips = rpc.get_chain_tips().unwrap();
+        assert_eq!(tips.len(), 2);
+        assert_eq!(tips[0].hash, fork[1]);
+        assert_eq!(tips[0].status, GetChainTipsResultStatus::Active);
+        assert_eq!(tips[1].hash, hashes[2]);
+        assert_eq!(tips[1].status, GetChainTipsResultStatus::ValidFork);
+        assert_eq!(tips[1].branch_length, 2);
+    }
+
+    #[test]
+    fn set_mock_time_and_generate_block_at() {
+        let rpc = Client::new(
+            "memory://set_mock_time_and_generate_block_at?time=1700000000",
+            bitcoincore_rpc::Auth::None,
+        )
+        .unwrap();
+        let address = Ledger::generate_credential_from_witness().address;
+        rpc.generate_to_address(1, &address).unwrap();
+
+        // Jump a week forward.
+        let time = 1700000000 + 7 * 24 * 60 * 60;
+        rpc.set_mock_time(time).unwrap();
+        let hash = rpc.generate_to_address(1, &address).unwrap()[0];
+        assert_eq!(rpc.get_block_header(&hash).unwrap().time as u64, time);
+
+        let hash = rpc.generate_block_at(&address, time as u32 + 60).unwrap();
+        assert_eq!(rpc.get_block_header(&hash).unwrap().time as u64, time + 60);
+
+        // Too far in the future.
+        assert!(rpc
+            .generate_block_at(&address, time as u32 + 3 * 60 * 60)
+            .is_err());
+        assert!(rpc.set_mock_time(u64::MAX).is_err());
+        assert_eq!(rpc.get_block_count().unwrap(), 3);
+    }
+
+    #[test]
+    fn generate_block() {
+        let rpc = Client::new("memory://generate_block", bitcoincore_rpc::Auth::None).unwrap();
+        let credential = Ledger::generate_credential_from_witness();
+        let address = credential.address.clone();
+        rpc.generate_to_address(103, &address).unwrap();
+
+        let spend = |height: u64| {
+            let coinbase = &rpc
+                .get_block(&rpc.get_block_hash(height).unwrap())
+                .unwrap()
+                .txdata[0];
+            let txin = TxIn {
+                previous_output: OutPoint {
+                    txid: coinbase.compute_txid(),
+                    vout: 0,
+                },
+                witness: credential.witness.clone().unwrap(),
+                ..Default::default()
+            };
+            let txout = TxOut {
+                value: coinbase.output[0].value - Amount::from_sat(0x45),
+                script_pubkey: address.script_pubkey(),
+            };
+
+            rpc.ledger.create_transaction(vec![txin], vec![txout])
+        };
+
+        let txs = [spend(1), spend(2), spend(3)];
+        rpc.send_raw_transaction(&txs[0]).unwrap();
+        rpc.send_raw_transaction(&txs[1]).unwrap();
+
+        // Only the given mempool transaction and the new transaction are
+        // mined, in given order.
+        let hash = rpc
+            .generate_block(&address, &[txs[2].clone(), txs[1].clone()])
+            .unwrap();
+        let block = rpc.get_block(&hash).unwrap();
+        assert_eq!(block.txdata[1..], [txs[2].clone(), txs[1].clone()]);
+        assert_eq!(
+            block.txdata[0].output[0].value,
+            rpc.ledger.get_block_subsidy(104).unwrap() + Amount::from_sat(0x45 * 2)
+        );
+        assert_eq!(rpc.ledger.get_mempool_transactions(), [txs[0].clone()]);
+
+        // Children can't come before their parents.
+        let child = rpc.ledger.create_transaction(
+            vec![TxIn {
+                previous_output: OutPoint {
+                    txid: txs[0].compute_txid(),
+                    vout: 0,
+                },
+                witness: credential.witness.clone().unwrap(),
+                ..Default::default()
+            }],
+            vec![TxOut {
+                value: Amount::from_sat(0x45),
+                script_pubkey: address.script_pubkey(),
+            }],
+        );
+        assert!(rpc
+            .generate_block(&address, std::slice::from_ref(&child))
+            .is_err());
+        assert!(rpc
+            .generate_block(&address, &[child.clone(), txs[0].clone()])
+            .is_err());
+        assert_eq!(rpc.get_block_count().unwrap(), 104);
+
+        rpc.generate_block(&address, &[txs[0].clone(), child])
+            .unwrap();
+        assert!(rpc.ledger.get_mempool_transactions().is_empty());
+    }
+
+    #[test]
+    fn generate_to_address_with_coinbase() {
+        let rpc = Client::new(
+            "generate_to_address_with_coinbase",
+            bitcoincore_rpc::Auth::None,
+        )
+        .unwrap();
+        let address = Ledger::generate_credential_from_witness().address;
+
+        let payout_script = ScriptBuf::from_bytes(vec![0x51]);
+        let extra_output = TxOut {
+            value: Amount::from_sat(0x45),
+            script_pubkey: address.script_pubkey(),
+        };
+        let options = CoinbaseOptions {
+            script_sig_data: b"mock pool".to_vec(),
+            payout_script: Some(payout_script.clone()),
+            extra_outputs: vec![extra_output.clone()],
+        };
+
+        // Heights up to 16 are pushed as opcodes, which
+        // `Block::bip34_block_height` can't parse.
+        rpc.generate_to_address(16, &address).unwrap();
+        let hashes = rpc
+            .generate_to_address_with_coinbase(2, &address, &options)
+            .unwrap();
+        assert_eq!(hashes.len(), 2);
+
+        for (height, hash) in (17..).zip(hashes.iter()) {
+            let block = rpc.get_block(hash).unwrap();
+            let coinbase = &block.txdata[0];
+
+            assert_eq!(block.bip34_block_height().unwrap(), height);
+            assert!(coinbase.input[0]
+                .script_sig
+                .as_bytes()
+                .ends_with(b"mock pool"));
+            assert_eq!(coinbase.output[0].script_pubkey, payout_script);
+            assert_eq!(coinbase.output[1], extra_output);
+        }
+    }
+
+    #[test]
+    fn snapshot_and_restore() {
+        let rpc = Client::new("snapshot_and_restore", bitcoincore_rpc::Auth::None).unwrap();
+        let address = Ledger::generate_credential_from_witness().address;
+
+        rpc.generate_to_address(101, &address).unwrap();
+        let id = rpc.snapshot().unwrap();
+
+        rpc.generate_to_address(10, &address).unwrap();
+        assert_eq!(rpc.get_block_count().unwrap(), 111);
+
+        rpc.restore(id).unwrap();
+        assert_eq!(rpc.get_block_count().unwrap(), 101);
+
+        assert!(rpc.restore(id + 1).is_err());
+    }
+
+    #[test]
+    fn fork() {
+        let rpc = Client::new("memory://client_fork", bitcoincore_rpc::Auth::None).unwrap();
+        let address = Ledger::generate_credential_from_witness().address;
+        rpc.generate_to_address(101, &address).unwrap();
+
+        let fork = rpc.fork("memory://client_fork_copy").unwrap();
+        assert_eq!(
+            fork.get_best_block_hash().unwrap(),
+            rpc.get_best_block_hash().unwrap()
+        );
+
+        fork.generate_to_address(1, &address).unwrap();
+        assert_eq!(fork.get_block_count().unwrap(), 102);
+        assert_eq!(rpc.get_block_count().unwrap(), 101);
+    }
+
+    #[test]
+    fn get_history_and_utxos() {
+        let rpc = Client::new("get_history_and_utxos", bitcoincore_rpc::Auth::None).unwrap();
+        let address = rpc.get_new_address(None, None).unwrap().assume_checked();
+        let script = address.script_pubkey();
+
+        assert!(rpc.get_history(&script).unwrap().is_empty());
+        assert!(rpc.get_utxos(&script).unwrap().is_empty());
+
+        let txid = rpc
+            .send_to_address(
+                &address,
+                Amount::from_sat(0x45),
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+            )
+            .unwrap();
+        assert_eq!(
+            rpc.get_history(&script).unwrap(),
+            vec![HistoryEntry { txid, height: None }]
+        );
+
+        let utxos = rpc.get_utxos(&script).unwrap();
+        assert_eq!(utxos.len(), 1);
+        assert_eq!(utxos[0].txid, txid);
+        assert_eq!(utxos[0].amount, Amount::from_sat(0x45));
+        assert_eq!(utxos[0].confirmations, 0);
+        assert_eq!(utxos[0].address.clone().unwrap().assume_checked(), address);
+
+        let miner = Ledger::generate_credential_from_witness().address;
+        rpc.generate_to_address(1, &miner).unwrap();
+        assert_eq!(
+            rpc.get_history(&script).unwrap(),
+            vec![HistoryEntry {
+                txid,
+                height: Some(1)
+            }]
+        );
+        assert_eq!(rpc.get_utxos(&script).unwrap()[0].confirmations, 1);
+    }
+}
@@ -6,8 +6,11 @@ use crate::ledger::Ledger;
 use bitcoincore_rpc::{Auth, RpcApi};
 use std::fmt::Debug;
 
+mod mock_api;
 mod rpc_api;
 
+pub use crate::ledger::{CoinbaseOptions, HistoryEntry};
+
 /// This trait defines non-functional interfaces for RPC interfaces, like
 /// `new()`. This is needed if target application wants to choose actual rpc and
 /// this via trait definitions. This is helpful for choosing different rpc
@@ -47,10 +50,20 @@ impl RpcApiWrapper for Client {
     /// Parameters must match `bitcoincore_rpc::Client::new()`. Only the `url`
     /// is used for database identification. Authorization struct is not used
     /// and can be a dummy value.
+    ///
+    /// If `url` is in the form of `memory://name`, ledger is kept in memory
+    /// and no files are created. Clients with the same `url` share the same
+    /// ledger, until every one of them is dropped.
+    ///
+    /// If `url` is in the form of `file:///path/to/chain.db`, ledger is kept
+    /// at that path and it is never cleaned. So, it survives restarts.
+    ///
+    /// Ledger settings can be given as `url` parameters, like
+    /// `memory://name?seed=42&time=1700000000&network=testnet`.
     #[tracing::instrument]
     fn new(url: &str, _auth: bitcoincore_rpc::Auth) -> bitcoincore_rpc::Result<Self> {
         Ok(Self {
-            ledger: Ledger::new(url),
+            ledger: Ledger::new(url)?,
         })
     }
 
@@ -61,7 +74,7 @@ impl RpcApiWrapper for Client {
     #[allow(deprecated)]
     fn new_without_cleanup(url: &str, _auth: Auth) -> bitcoincore_rpc::Result<Self> {
         Ok(Self {
-            ledger: Ledger::new_without_cleanup(url),
+            ledger: Ledger::new_without_cleanup(url)?,
         })
     }
 }
@@ -69,10 +82,57 @@ impl RpcApiWrapper for Client {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use bitcoin::Amount;
 
     /// Creating a new `Client` with dummy parameters should not panic.
     #[test]
     fn new() {
         let _should_not_panic = Client::new("client_new", bitcoincore_rpc::Auth::None).unwrap();
     }
+
+    /// In-memory clients with the same name should share the ledger.
+    #[test]
+    fn new_in_memory() {
+        let client = Client::new("memory://client_new_in_memory", Auth::None).unwrap();
+        let address = client.get_new_address(None, None).unwrap().assume_checked();
+        client.generate_to_address(1, &address).unwrap();
+
+        let other = Client::new("memory://client_new_in_memory", Auth::None).unwrap();
+        assert_eq!(other.get_block_count().unwrap(), 1);
+        assert_eq!(client.clone().get_block_count().unwrap(), 1);
+    }
+
+    /// Clients with the same seed and time should create the same chain.
+    #[test]
+    fn new_deterministic() {
+        let clients = ["client_new_deterministic", "client_new_deterministic_other"].map(|name| {
+            Client::new(
+                &format!("memory://{name}?seed=42&time=1700000000"),
+                Auth::None,
+            )
+            .unwrap()
+        });
+
+        let [hashes, other_hashes] = clients.each_ref().map(|client| {
+            let address = client.get_new_address(None, None).unwrap().assume_checked();
+            client
+                .send_to_address(
+                    &address,
+                    Amount::from_sat(0x45),
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            client.generate_to_address(3, &address).unwrap()
+        });
+        assert_eq!(hashes, other_hashes);
+
+        let block = clients[0].get_block(&hashes[0]).unwrap();
+        assert_eq!(block.header.time, 1700000000);
+    }
 }
@@ -11,20 +11,22 @@ use crate::{
 use bitcoin::{
     address::NetworkChecked,
     consensus::{encode, serialize, Encodable},
+    constants::MAX_BLOCK_SIGOPS_COST,
     hashes::Hash,
     params::Params,
-    Address, Amount, BlockHash, OutPoint, SignedAmount, Transaction, TxIn, TxOut, Txid,
+    Address, Amount, BlockHash, OutPoint, ScriptBuf, SignedAmount, Transaction, TxIn, TxOut, Txid,
+    Weight,
 };
 use bitcoincore_rpc::{
     json::{
-        self, GetChainTipsResultStatus, GetRawTransactionResult, GetRawTransactionResultVin,
+        self, GetRawTransactionResult, GetRawTransactionResultVin,
         GetRawTransactionResultVinScriptSig, GetRawTransactionResultVout,
         GetRawTransactionResultVoutScriptPubKey, GetTransactionResult, GetTransactionResultDetail,
         GetTransactionResultDetailCategory, GetTxOutResult, SignRawTransactionResult, WalletTxInfo,
     },
     Error, RpcApi,
 };
-use secp256k1::rand::{self, RngCore};
+use std::collections::HashMap;
 
 impl RpcApi for Client {
     /// TL;DR: If this function is called for `cmd`, it's corresponding mock is
@@ -208,6 +210,7 @@ impl RpcApi for Client {
         }
 
         let raw_tx = self.get_raw_transaction(txid, None).unwrap();
+        let network = self.ledger.get_network()?;
         let mut amount = Amount::from_sat(0);
 
         let details: Vec<GetTransactionResultDetail> = raw_tx
@@ -215,13 +218,11 @@ impl RpcApi for Client {
             .iter()
             .map(|output| {
                 amount += output.value;
-                let address = match Address::from_script(
-                    &output.script_pubkey,
-                    Params::new(bitcoin::Network::Regtest),
-                ) {
-                    Ok(a) => Some(a.as_unchecked().clone()),
-                    Err(_) => None,
-                };
+                let address =
+                    match Address::from_script(&output.script_pubkey, Params::new(network)) {
+                        Ok(a) => Some(a.as_unchecked().clone()),
+                        Err(_) => None,
+                    };
 
                 GetTransactionResultDetail {
                     address,
@@ -266,7 +267,8 @@ impl RpcApi for Client {
     }
 
     /// Sends specified amount to `address` regardless of the user balance.
-    /// Meaning: Unlimited free money.
+    /// Meaning: Unlimited free money. `address` must be valid for the
+    /// ledger's network.
     ///
     /// Reason this call behaves like this is there are no wallet
     /// implementation. This is intended way to generate inputs for other
@@ -320,14 +322,15 @@ impl RpcApi for Client {
             )));
         }
 
+        self.ledger.check_address(address)?;
+
         // First, create a random input. Why? Because calling this function for
         // same amount twice will trigger a database error about same TXID blah,
-        // blah, blah.
-        let rn = rand::thread_rng().next_u64();
-        let txin = self.ledger.create_txin(
-            Txid::hash(&[(rn & 0xFF) as u8]),
-            (rn & (u32::MAX as u64)) as u32,
-        );
+        // blah, blah. Randomness comes from the ledger, so that it is
+        // deterministic for a seeded ledger.
+        let random = self.ledger.get_random_bytes()?;
+        let vout = u32::from_le_bytes(random[..4].try_into().unwrap());
+        let txin = self.ledger.create_txin(Txid::from_byte_array(random), vout);
 
         let txout = self.ledger.create_txout(amount, address.script_pubkey());
         let tx = self.ledger.create_transaction(vec![txin], vec![txout]);
@@ -335,9 +338,9 @@ impl RpcApi for Client {
         Ok(self.ledger.add_transaction_unconditionally(tx)?)
     }
 
-    /// Creates a random secret/public key pair and generates a Bitcoin address
-    /// from witness program. Please note that this address is not hold in
-    /// ledger in any way.
+    /// Returns a constant Bitcoin address from witness program, for the
+    /// ledger's network. Please note that this address is not hold in ledger
+    /// in any way.
     #[tracing::instrument(skip_all)]
     fn get_new_address(
         &self,
@@ -357,19 +360,26 @@ impl RpcApi for Client {
             )));
         }
 
-        let address = ledger::Ledger::get_constant_credential_from_witness().address;
+        let script_pubkey = ledger::Ledger::get_constant_credential_from_witness()
+            .address
+            .script_pubkey();
+        let address = Address::from_script(&script_pubkey, self.ledger.get_network()?)
+            .map_err(|e| LedgerError::Address(e.to_string()))?;
 
-        Ok(address.as_unchecked().to_owned())
+        Ok(address.into_unchecked())
     }
 
     /// Generates `block_num` amount of block rewards to `address`. Also mines
-    /// current mempool transactions to a block.
+    /// current mempool transactions to a block. `address` must be valid for
+    /// the ledger's network.
     #[tracing::instrument(skip_all)]
     fn generate_to_address(
         &self,
         block_num: u64,
         address: &Address<NetworkChecked>,
     ) -> bitcoincore_rpc::Result<Vec<bitcoin::BlockHash>> {
+        self.ledger.check_address(address)?;
+
         let mut hashes: Vec<BlockHash> = Vec::new();
 
         for _ in 0..block_num {
@@ -380,10 +390,11 @@ impl RpcApi for Client {
     }
 
     /// This function is intended for retrieving information about a txout's
-    /// value and confirmation. Other data may not be reliable.
+    /// value, script, confirmation and coinbase flag. Other data may not be
+    /// reliable.
     ///
     /// This will include mempool txouts regardless of the `include_mempool`
-    /// flag. `coinbase` will be set to false regardless if it is or not.
+    /// flag.
     #[tracing::instrument(skip_all)]
     fn get_tx_out(
         &self,
@@ -399,30 +410,33 @@ impl RpcApi for Client {
         }
 
         let utxo = OutPoint { txid: *txid, vout };
-        if self.ledger.is_utxo_spent(utxo) {
-            return Err(LedgerError::Utxo(format!("UTXO {utxo:?} is spent")).into());
-        }
+        let utxo = match self.ledger.get_utxo(utxo) {
+            Ok(utxo) => utxo,
+            Err(_) => return Err(LedgerError::Utxo(format!("UTXO {utxo:?} is spent")).into()),
+        };
 
         let bestblock = self.get_best_block_hash()?;
 
-        let tx = self.get_raw_transaction(txid, None)?;
-        let value = tx.output.get(vout as usize).unwrap().value;
+        // Mempool outputs have the next block's height, hence no confirmations.
+        let confirmations = (self.ledger.get_block_height()? + 1).saturating_sub(utxo.height);
 
-        let confirmations = self.get_transaction(txid, None)?.info.confirmations as u32;
+        let script_pubkey = utxo.txout.script_pubkey;
 
         Ok(Some(GetTxOutResult {
             bestblock,
             confirmations,
-            value,
+            value: utxo.txout.value,
             script_pub_key: GetRawTransactionResultVoutScriptPubKey {
-                asm: "TODO".to_string(),
-                hex: Vec::new(),
+                asm: script_pubkey.to_asm_string(),
+                hex: script_pubkey.to_bytes(),
                 req_sigs: None,
                 type_: None,
                 addresses: Vec::new(),
-                address: None,
+                address: Address::from_script(&script_pubkey, self.ledger.get_network()?)
+                    .ok()
+                    .map(|address| address.into_unchecked()),
             },
-            coinbase: false,
+            coinbase: utxo.coinbase,
         }))
     }
 
@@ -447,6 +461,74 @@ impl RpcApi for Client {
     ) -> bitcoincore_rpc::Result<bitcoin::block::Header> {
         Ok(self.ledger.get_block_with_hash(*hash)?.header)
     }
+    /// Block can be in a stale branch. Those have -1 confirmations and no next
+    /// block hash.
+    #[tracing::instrument(skip_all)]
+    fn get_block_header_info(
+        &self,
+        hash: &bitcoin::BlockHash,
+    ) -> bitcoincore_rpc::Result<json::GetBlockHeaderResult> {
+        let block = self.ledger.get_block_with_hash(*hash)?;
+        let (height, _) = self.ledger.get_block_height_and_time(*hash)?;
+        let header = block.header;
+
+        Ok(json::GetBlockHeaderResult {
+            hash: *hash,
+            confirmations: self.ledger.get_block_confirmations(*hash)?,
+            height: height as usize,
+            version: header.version,
+            version_hex: Some(header.version.to_consensus().to_be_bytes().to_vec()),
+            merkle_root: header.merkle_root,
+            time: header.time as usize,
+            median_time: Some(self.ledger.get_median_time_past(*hash)? as usize),
+            nonce: header.nonce,
+            bits: format!("{:08x}", header.bits.to_consensus()),
+            difficulty: header.difficulty_float(),
+            chainwork: self.ledger.get_chain_work(*hash)?.to_be_bytes().to_vec(),
+            n_tx: block.txdata.len(),
+            previous_block_hash: match height {
+                0 => None,
+                _ => Some(self.ledger.get_previous_block_hash(&header)?),
+            },
+            next_block_hash: self.ledger.get_next_block_hash(*hash)?,
+        })
+    }
+    /// Block can be in a stale branch. Those have -1 confirmations and no next
+    /// block hash.
+    #[tracing::instrument(skip_all)]
+    fn get_block_info(
+        &self,
+        hash: &bitcoin::BlockHash,
+    ) -> bitcoincore_rpc::Result<json::GetBlockResult> {
+        let block = self.ledger.get_block_with_hash(*hash)?;
+        let header_info = self.get_block_header_info(hash)?;
+
+        // Weight is 3 times the stripped size plus the total size.
+        let size = block.total_size();
+        let weight = block.weight().to_wu() as usize;
+
+        Ok(json::GetBlockResult {
+            hash: *hash,
+            confirmations: header_info.confirmations,
+            size,
+            strippedsize: Some((weight - size) / 3),
+            weight,
+            height: header_info.height,
+            version: header_info.version.to_consensus(),
+            version_hex: header_info.version_hex,
+            merkleroot: header_info.merkle_root,
+            tx: block.txdata.iter().map(|tx| tx.compute_txid()).collect(),
+            time: header_info.time,
+            mediantime: header_info.median_time,
+            nonce: header_info.nonce,
+            bits: header_info.bits,
+            difficulty: header_info.difficulty,
+            chainwork: header_info.chainwork,
+            n_tx: header_info.n_tx,
+            previousblockhash: header_info.previous_block_hash,
+            nextblockhash: header_info.next_block_hash,
+        })
+    }
 
     #[tracing::instrument(skip_all)]
     fn get_block_count(&self) -> bitcoincore_rpc::Result<u64> {
@@ -612,21 +694,7 @@ impl RpcApi for Client {
 
     #[tracing::instrument(skip_all)]
     fn get_chain_tips(&self) -> bitcoincore_rpc::Result<json::GetChainTipsResult> {
-        let height = self.ledger.get_block_height().unwrap();
-        let hash = if height == 0 {
-            BlockHash::all_zeros()
-        } else {
-            self.ledger.get_block_with_height(height)?.block_hash()
-        };
-
-        let tip = json::GetChainTipsResultTip {
-            height: height as u64,
-            hash,
-            branch_length: height as usize,
-            status: GetChainTipsResultStatus::Active,
-        };
-
-        Ok(vec![tip])
+        Ok(self.ledger.get_chain_tips()?)
     }
 
     #[tracing::instrument(skip_all)]
@@ -636,16 +704,155 @@ impl RpcApi for Client {
             .get_block_with_height(height as u32)?
             .block_hash())
     }
+
+    #[tracing::instrument(skip_all)]
+    fn invalidate_block(&self, block_hash: &bitcoin::BlockHash) -> bitcoincore_rpc::Result<()> {
+        Ok(self.ledger.invalidate_block(*block_hash)?)
+    }
+
+    #[tracing::instrument(skip_all)]
+    fn reconsider_block(&self, block_hash: &bitcoin::BlockHash) -> bitcoincore_rpc::Result<()> {
+        Ok(self.ledger.reconsider_block(*block_hash)?)
+    }
+
+    /// Block is validated before it is added on top of the active chain.
+    /// Blocks that don't extend the tip are rejected.
+    #[tracing::instrument(skip_all)]
+    fn submit_block(&self, block: &bitcoin::Block) -> bitcoincore_rpc::Result<()> {
+        self.ledger.submit_block(block.clone())?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip_all)]
+    fn submit_block_hex(&self, block_hex: &str) -> bitcoincore_rpc::Result<()> {
+        let block = encode::deserialize_hex::<bitcoin::Block>(block_hex)?;
+
+        self.submit_block(&block)
+    }
+
+    /// Template is built on top of the tip of the active chain, with the
+    /// mempool transactions that would be mined by `generate_to_address`.
+    /// `segwit` rule must be given, like Bitcoin Core requires.
+    ///
+    /// `bitcoincore_rpc` only has the `template` mode and no capabilities, so
+    /// both of them are always the defaults.
+    #[tracing::instrument(skip_all)]
+    fn get_block_template(
+        &self,
+        mode: json::GetBlockTemplateModes,
+        rules: &[json::GetBlockTemplateRules],
+        capabilities: &[json::GetBlockTemplateCapabilities],
+    ) -> bitcoincore_rpc::Result<json::GetBlockTemplateResult> {
+        // These won't compile anymore if `bitcoincore_rpc` adds a new mode or
+        // capability, which should be handled then.
+        let json::GetBlockTemplateModes::Template = mode;
+        if let Some(capability) = capabilities.first() {
+            match *capability {}
+        }
+
+        if !rules.contains(&json::GetBlockTemplateRules::SegWit) {
+            return Err(Error::ReturnedError(
+                "getblocktemplate must be called with the segwit rule set".to_string(),
+            ));
+        }
+
+        let tip_height = self.ledger.get_block_height()?;
+        let tip = self.ledger.get_block_hash(tip_height)?;
+        let height = tip_height + 1;
+
+        let mempool = self.ledger.get_mempool_transactions();
+        let transactions = self.ledger.select_block_transactions(&mempool)?;
+        let txids: Vec<Txid> = transactions.iter().map(|tx| tx.compute_txid()).collect();
+
+        let mut fees = Amount::ZERO;
+        let mut template_transactions = Vec::new();
+        for transaction in &transactions {
+            let fee = self.ledger.calculate_transaction_fee(transaction);
+            fees += fee;
+
+            // Dependencies are 1-based indexes of the template transactions.
+            let mut depends: Vec<u32> = transaction
+                .input
+                .iter()
+                .filter_map(|input| {
+                    txids
+                        .iter()
+                        .position(|txid| *txid == input.previous_output.txid)
+                        .map(|index| index as u32 + 1)
+                })
+                .collect();
+            depends.sort_unstable();
+            depends.dedup();
+
+            template_transactions.push(json::GetBlockTemplateResultTransaction {
+                txid: transaction.compute_txid(),
+                wtxid: transaction.compute_wtxid(),
+                raw_tx: serialize(transaction),
+                fee,
+                sigops: self.ledger.get_transaction_sigops_cost(transaction) as u32,
+                weight: transaction.weight().to_wu() as usize,
+                depends,
+            });
+        }
+
+        let target = ledger::Ledger::get_block_target();
+
+        Ok(json::GetBlockTemplateResult {
+            bits: target
+                .to_compact_lossy()
+                .to_consensus()
+                .to_be_bytes()
+                .to_vec(),
+            previous_block_hash: tip,
+            current_time: self.ledger.get_block_time(height)? as u64,
+            height: height as u64,
+            sigop_limit: MAX_BLOCK_SIGOPS_COST as u32,
+            size_limit: Weight::MAX_BLOCK.to_wu() as u32,
+            weight_limit: Weight::MAX_BLOCK.to_wu() as u32,
+            version: ledger::Ledger::get_block_version().to_consensus() as u32,
+            rules: vec![
+                json::GetBlockTemplateResultRules::Csv,
+                json::GetBlockTemplateResultRules::SegWit,
+                json::GetBlockTemplateResultRules::Taproot,
+            ],
+            capabilities: vec![],
+            version_bits_available: HashMap::new(),
+            version_bits_required: 0,
+            longpollid: format!("{}{}", tip, mempool.len()),
+            transactions: template_transactions,
+            signet_challenge: ScriptBuf::new(),
+            default_witness_commitment: ledger::Ledger::create_witness_commitment_script(
+                transactions.iter().map(|tx| tx.compute_wtxid()).collect(),
+                [0u8; 32],
+            )?,
+            coinbaseaux: HashMap::new(),
+            coinbase_value: self.ledger.get_block_subsidy(height)? + fees,
+            target: target.to_be_bytes().to_vec(),
+            min_time: self.ledger.get_median_time_past(tip)? as u64 + 1,
+            mutable: vec![
+                json::GetBlockTemplateResulMutations::Time,
+                json::GetBlockTemplateResulMutations::Transactions,
+                json::GetBlockTemplateResulMutations::PreviousBlock,
+            ],
+            nonce_range: vec![0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff],
+        })
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{ledger::Ledger, utils::_decode_from_hex, Client, RpcApiWrapper};
+    use crate::{ledger::Ledger, utils::_decode_from_hex, Client, CoinbaseOptions, RpcApiWrapper};
     use bitcoin::{
-        consensus::{deserialize, Decodable},
-        Amount, Network, OutPoint, Transaction, TxIn,
+        absolute,
+        block::{Header, Version},
+        consensus::{deserialize, encode, Decodable},
+        hashes::Hash,
+        script::Builder,
+        transaction, Amount, Block, CompactTarget, Network, OutPoint, Transaction, TxIn,
+        TxMerkleNode, TxOut, Witness,
     };
-    use bitcoincore_rpc::RpcApi;
+    use bitcoincore_rpc::{json, RpcApi};
 
     #[test]
     fn send_get_raw_transaction() {
@@ -791,6 +998,30 @@ mod tests {
         assert_eq!(txid, tx.info.txid);
     }
 
+    #[test]
+    fn get_tx_out() {
+        let rpc = Client::new("get_tx_out", bitcoincore_rpc::Auth::None).unwrap();
+
+        let address = rpc.get_new_address(None, None).unwrap().assume_checked();
+        let block_hash = rpc.generate_to_address(1, &address).unwrap()[0];
+        let coinbase = rpc.get_block(&block_hash).unwrap().txdata[0].clone();
+        let txid = coinbase.compute_txid();
+
+        let txout = rpc.get_tx_out(&txid, 0, None).unwrap().unwrap();
+        assert_eq!(txout.value, coinbase.output[0].value);
+        assert_eq!(txout.script_pub_key.hex, address.script_pubkey().to_bytes());
+        assert_eq!(txout.confirmations, 1);
+        assert!(txout.coinbase);
+
+        rpc.generate_to_address(2, &address).unwrap();
+        let txout = rpc.get_tx_out(&txid, 0, None).unwrap().unwrap();
+        assert_eq!(txout.confirmations, 3);
+
+        assert!(rpc
+            .get_tx_out(&txid, coinbase.output.len() as u32, None)
+            .is_err());
+    }
+
     #[test]
     fn send_to_address() {
         let rpc = Client::new("send_to_address", bitcoincore_rpc::Auth::None).unwrap();
@@ -832,6 +1063,47 @@ mod tests {
         assert!(!address.is_valid_for_network(Network::Bitcoin));
     }
 
+    #[test]
+    fn get_new_address_with_network() {
+        let rpc = Client::new(
+            "memory://get_new_address_with_network?network=signet",
+            bitcoincore_rpc::Auth::None,
+        )
+        .unwrap();
+
+        let address = rpc.get_new_address(None, None).unwrap();
+        assert!(address.is_valid_for_network(Network::Signet));
+        assert!(!address.is_valid_for_network(Network::Regtest));
+
+        let address = address.require_network(Network::Signet).unwrap();
+        let block_hash = rpc.generate_to_address(1, &address).unwrap()[0];
+        let coinbase = rpc.get_block(&block_hash).unwrap().txdata[0].clone();
+        let txout = rpc
+            .get_tx_out(&coinbase.compute_txid(), 0, None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            txout.script_pub_key.address.unwrap().assume_checked(),
+            address
+        );
+
+        // Addresses of other networks are rejected.
+        let regtest_address = Ledger::generate_credential_from_witness().address;
+        assert!(rpc.generate_to_address(1, &regtest_address).is_err());
+        assert!(rpc
+            .send_to_address(
+                &regtest_address,
+                Amount::from_sat(0x45),
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+            )
+            .is_err());
+    }
+
     #[test]
     fn generate_to_address() {
         let rpc = Client::new("generate_to_address", bitcoincore_rpc::Auth::None).unwrap();
@@ -852,12 +1124,7 @@ mod tests {
         // Wallet has funds now. It should not be rejected.
         let txin = TxIn {
             previous_output: OutPoint {
-                txid: rpc
-                    .ledger
-                    ._get_transactions()
-                    .first()
-                    .unwrap()
-                    .compute_txid(),
+                txid: rpc.ledger.get_block_with_height(1).unwrap().txdata[0].compute_txid(),
                 vout: 0,
             },
             witness: credential.witness.unwrap(),
@@ -875,6 +1142,12 @@ mod tests {
         let rpc = Client::new("get_best_block_hash", bitcoincore_rpc::Auth::None).unwrap();
         let address = Ledger::generate_credential_from_witness().address;
 
+        // Fresh chain only has the regtest genesis block.
+        assert_eq!(
+            rpc.get_best_block_hash().unwrap(),
+            bitcoin::constants::genesis_block(Network::Regtest).block_hash()
+        );
+
         let tx = rpc.ledger.create_transaction(vec![], vec![]);
         rpc.ledger.add_transaction_unconditionally(tx).unwrap();
         let block_hash = rpc.ledger.mine_block(&address).unwrap();
@@ -914,6 +1187,69 @@ mod tests {
         assert_eq!(block.header, block_header);
     }
 
+    #[test]
+    fn get_block_header_info_and_block_info() {
+        let rpc = Client::new(
+            "memory://get_block_header_info_and_block_info",
+            bitcoincore_rpc::Auth::None,
+        )
+        .unwrap();
+        let address = Ledger::generate_credential_from_witness().address;
+
+        let genesis_hash = rpc.get_block_hash(0).unwrap();
+        let genesis = rpc.get_block_header_info(&genesis_hash).unwrap();
+        assert_eq!(genesis.height, 0);
+        assert_eq!(genesis.confirmations, 1);
+        assert_eq!(genesis.bits, "207fffff");
+        assert_eq!(genesis.previous_block_hash, None);
+        assert_eq!(genesis.next_block_hash, None);
+        // Regtest genesis block has 2 units of work.
+        assert_eq!(genesis.chainwork[31], 2);
+
+        let tx = rpc.ledger.create_transaction(vec![], vec![]);
+        let txid = rpc.ledger.add_transaction_unconditionally(tx).unwrap();
+        let hashes = rpc.generate_to_address(3, &address).unwrap();
+
+        let header_info = rpc.get_block_header_info(&hashes[0]).unwrap();
+        let header = rpc.get_block_header(&hashes[0]).unwrap();
+        assert_eq!(header_info.hash, hashes[0]);
+        assert_eq!(header_info.height, 1);
+        assert_eq!(header_info.confirmations, 3);
+        assert_eq!(header_info.version, header.version);
+        assert_eq!(header_info.merkle_root, header.merkle_root);
+        assert_eq!(header_info.time, header.time as usize);
+        assert_eq!(header_info.nonce, header.nonce);
+        assert_eq!(header_info.difficulty, header.difficulty_float());
+        assert_eq!(header_info.n_tx, 2);
+        assert_eq!(header_info.previous_block_hash, Some(genesis_hash));
+        assert_eq!(header_info.next_block_hash, Some(hashes[1]));
+        assert_eq!(
+            header_info.chainwork,
+            (header.work() + header.work()).to_be_bytes().to_vec()
+        );
+        assert!(header_info.median_time.unwrap() <= header_info.time);
+
+        let block_info = rpc.get_block_info(&hashes[0]).unwrap();
+        let block = rpc.get_block(&hashes[0]).unwrap();
+        assert_eq!(block_info.tx, vec![block.txdata[0].compute_txid(), txid]);
+        assert_eq!(block_info.size, block.total_size());
+        assert_eq!(block_info.weight, block.weight().to_wu() as usize);
+        assert_eq!(block_info.chainwork, header_info.chainwork);
+        assert_eq!(block_info.nextblockhash, Some(hashes[1]));
+
+        // Stale blocks are not confirmed.
+        rpc.invalidate_block(&hashes[2]).unwrap();
+        let header_info = rpc.get_block_header_info(&hashes[2]).unwrap();
+        assert_eq!(header_info.confirmations, -1);
+        assert_eq!(header_info.height, 3);
+        assert_eq!(
+            rpc.get_block_header_info(&hashes[1])
+                .unwrap()
+                .next_block_hash,
+            None
+        );
+    }
+
     #[test]
     fn get_block_count() {
         let rpc = Client::new("get_block_count", bitcoincore_rpc::Auth::None).unwrap();
@@ -928,6 +1264,415 @@ mod tests {
         assert_eq!(rpc.get_block_count().unwrap(), 1);
     }
 
+    #[test]
+    fn invalidate_and_reconsider_block() {
+        let rpc = Client::new(
+            "invalidate_and_reconsider_block",
+            bitcoincore_rpc::Auth::None,
+        )
+        .unwrap();
+        let address = Ledger::generate_credential_from_witness().address;
+
+        let tx = rpc.ledger.create_transaction(vec![], vec![]);
+        let txid = rpc.ledger.add_transaction_unconditionally(tx).unwrap();
+        let block_hash = rpc.ledger.mine_block(&address).unwrap();
+        rpc.ledger.mine_block(&address).unwrap();
+        assert_eq!(rpc.get_block_count().unwrap(), 2);
+
+        rpc.invalidate_block(&block_hash).unwrap();
+        assert_eq!(rpc.get_block_count().unwrap(), 0);
+        assert!(rpc.ledger.get_mempool_transaction(txid).is_some());
+
+        rpc.reconsider_block(&block_hash).unwrap();
+        assert_eq!(rpc.get_block_count().unwrap(), 2);
+        assert!(rpc.ledger.get_mempool_transaction(txid).is_none());
+    }
+
+    #[test]
+    fn submit_block() {
+        let rpc = Client::new("memory://submit_block", bitcoincore_rpc::Auth::None).unwrap();
+        let credential = Ledger::generate_credential_from_witness();
+        let address = credential.address.clone();
+        rpc.generate_to_address(101, &address).unwrap();
+
+        let coinbase = rpc
+            .get_block(&rpc.get_block_hash(1).unwrap())
+            .unwrap()
+            .txdata[0]
+            .clone();
+        let tx = rpc.ledger.create_transaction(
+            vec![TxIn {
+                previous_output: OutPoint {
+                    txid: coinbase.compute_txid(),
+                    vout: 0,
+                },
+                witness: credential.witness.unwrap(),
+                ..Default::default()
+            }],
+            vec![rpc
+                .ledger
+                .create_txout(Amount::from_sat(0x45), address.script_pubkey())],
+        );
+        let fee = coinbase.output[0].value - Amount::from_sat(0x45);
+
+        // Builds a block like an external miner would.
+        let build_block = |transactions: Vec<Transaction>, fees: Amount| {
+            let tip = rpc
+                .get_block_header(&rpc.get_best_block_hash().unwrap())
+                .unwrap();
+            let coinbase = rpc
+                .ledger
+                .create_coinbase_transaction(
+                    &address,
+                    transactions.iter().map(|tx| tx.compute_wtxid()).collect(),
+                    rpc.get_block_count().unwrap() as u32 + 1,
+                    fees,
+                    &CoinbaseOptions::default(),
+                )
+                .unwrap();
+            let mut block = Block {
+                header: Header {
+                    version: Version::TWO,
+                    prev_blockhash: tip.block_hash(),
+                    merkle_root: TxMerkleNode::all_zeros(),
+                    time: tip.time + 600,
+                    bits: tip.bits,
+                    nonce: 0,
+                },
+                txdata: [vec![coinbase], transactions].concat(),
+            };
+            block.header.merkle_root = block.compute_merkle_root().unwrap();
+
+            block
+        };
+        let grind = |block: &mut Block, valid: bool| {
+            while block.header.validate_pow(block.header.target()).is_ok() != valid {
+                block.header.nonce += 1;
+            }
+        };
+
+        let mut block = build_block(vec![tx.clone()], fee);
+        grind(&mut block, true);
+
+        // Invalid proof of work.
+        let mut invalid = block.clone();
+        grind(&mut invalid, false);
+        assert!(rpc.submit_block(&invalid).is_err());
+
+        // Invalid merkle root.
+        let mut invalid = block.clone();
+        invalid.header.merkle_root = TxMerkleNode::all_zeros();
+        grind(&mut invalid, true);
+        assert!(rpc.submit_block(&invalid).is_err());
+
+        // Coinbase claims more than allowed.
+        let mut invalid = build_block(vec![tx.clone()], fee + Amount::ONE_SAT);
+        grind(&mut invalid, true);
+        assert!(rpc.submit_block(&invalid).is_err());
+
+        // Invalid transaction.
+        let mut invalid_tx = tx.clone();
+        invalid_tx.input[0].previous_output.vout = 1;
+        let mut invalid = build_block(vec![invalid_tx], Amount::ZERO);
+        grind(&mut invalid, true);
+        assert!(rpc.submit_block(&invalid).is_err());
+
+        // Invalid witness commitment.
+        let mut invalid = block.clone();
+        invalid.txdata[0].output.last_mut().unwrap().script_pubkey =
+            Ledger::create_witness_commitment_script(vec![], [0u8; 32]).unwrap();
+        invalid.header.merkle_root = invalid.compute_merkle_root().unwrap();
+        grind(&mut invalid, true);
+        assert!(rpc.submit_block(&invalid).is_err());
+
+        assert_eq!(rpc.get_block_count().unwrap(), 101);
+
+        rpc.submit_block_hex(&encode::serialize_hex(&block))
+            .unwrap();
+        assert_eq!(rpc.get_best_block_hash().unwrap(), block.block_hash());
+        assert_eq!(
+            rpc.ledger
+                .get_transaction_block_height(&tx.compute_txid())
+                .unwrap(),
+            102
+        );
+
+        // Same block can't be submitted twice and blocks must extend the tip.
+        assert!(rpc.submit_block(&block).is_err());
+        let mut stale = block.clone();
+        stale.header.time += 1;
+        grind(&mut stale, true);
+        assert!(rpc.submit_block(&stale).is_err());
+
+        // Mempool transactions are accepted as they are, so a block mined by a
+        // peer with the same mempool is valid. Transaction of
+        // `send_to_address` spends an unknown output.
+        rpc.send_to_address(
+            &address,
+            Amount::from_sat(0x45),
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+        let peer = rpc.fork("memory://submit_block_peer").unwrap();
+        peer.generate_to_address(1, &address).unwrap();
+        let mined = peer
+            .get_block(&peer.get_best_block_hash().unwrap())
+            .unwrap();
+        assert_eq!(mined.txdata.len(), 2);
+        rpc.submit_block(&mined).unwrap();
+        assert_eq!(rpc.get_best_block_hash().unwrap(), mined.block_hash());
+        assert!(rpc.ledger.get_mempool_transactions().is_empty());
+
+        // Blocks with mempool transactions that are mined elsewhere.
+        let coinbase = rpc
+            .get_block(&rpc.get_block_hash(2).unwrap())
+            .unwrap()
+            .txdata[0]
+            .clone();
+        let mut mempool_tx = tx.clone();
+        mempool_tx.input[0].previous_output.txid = coinbase.compute_txid();
+        rpc.ledger.add_transaction(mempool_tx.clone()).unwrap();
+        let mut block = build_block(vec![mempool_tx], fee);
+        grind(&mut block, true);
+        rpc.submit_block(&block).unwrap();
+        assert!(rpc.ledger.get_mempool_transactions().is_empty());
+    }
+
+    #[test]
+    fn get_block_template() {
+        let rpc = Client::new("memory://get_block_template", bitcoincore_rpc::Auth::None).unwrap();
+        let credential = Ledger::generate_credential_from_witness();
+        let address = credential.address.clone();
+        rpc.generate_to_address(101, &address).unwrap();
+
+        assert!(rpc
+            .get_block_template(json::GetBlockTemplateModes::Template, &[], &[])
+            .is_err());
+
+        // A parent and a child that spends it.
+        let coinbase = rpc
+            .get_block(&rpc.get_block_hash(1).unwrap())
+            .unwrap()
+            .txdata[0]
+            .clone();
+        let parent = rpc.ledger.create_transaction(
+            vec![TxIn {
+                previous_output: OutPoint {
+                    txid: coinbase.compute_txid(),
+                    vout: 0,
+                },
+                witness: credential.witness.clone().unwrap(),
+                ..Default::default()
+            }],
+            vec![rpc
+                .ledger
+                .create_txout(Amount::ONE_BTC, address.script_pubkey())],
+        );
+        let child = rpc.ledger.create_transaction(
+            vec![TxIn {
+                previous_output: OutPoint {
+                    txid: parent.compute_txid(),
+                    vout: 0,
+                },
+                witness: credential.witness.clone().unwrap(),
+                ..Default::default()
+            }],
+            vec![rpc
+                .ledger
+                .create_txout(Amount::from_sat(0x45), address.script_pubkey())],
+        );
+        rpc.send_raw_transaction(&parent).unwrap();
+        rpc.send_raw_transaction(&child).unwrap();
+        let fees = coinbase.output[0].value - Amount::from_sat(0x45);
+
+        let template = rpc
+            .get_block_template(
+                json::GetBlockTemplateModes::Template,
+                &[json::GetBlockTemplateRules::SegWit],
+                &[],
+            )
+            .unwrap();
+        let tip = rpc.get_best_block_hash().unwrap();
+        assert_eq!(template.previous_block_hash, tip);
+        assert_eq!(template.height, 102);
+        assert_eq!(template.transactions.len(), 2);
+        assert_eq!(template.transactions[0].txid, parent.compute_txid());
+        assert!(template.transactions[0].depends.is_empty());
+        assert_eq!(template.transactions[1].txid, child.compute_txid());
+        assert_eq!(template.transactions[1].depends, vec![1]);
+        assert_eq!(
+            template
+                .transactions
+                .iter()
+                .map(|tx| tx.fee)
+                .sum::<Amount>(),
+            fees
+        );
+        assert_eq!(
+            template.coinbase_value,
+            rpc.ledger.get_block_subsidy(102).unwrap() + fees
+        );
+        assert_eq!(
+            template.target,
+            Ledger::get_block_target().to_be_bytes().to_vec()
+        );
+
+        // Build a block from the template, like a pool would.
+        let coinbase = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                script_sig: Builder::new()
+                    .push_int(template.height as i64)
+                    .push_slice(b"pool")
+                    .into_script(),
+                witness: Witness::from_slice(&[[0u8; 32]]),
+                ..Default::default()
+            }],
+            output: vec![
+                TxOut {
+                    value: template.coinbase_value,
+                    script_pubkey: address.script_pubkey(),
+                },
+                TxOut {
+                    value: Amount::ZERO,
+                    script_pubkey: template.default_witness_commitment.clone(),
+                },
+            ],
+        };
+        let transactions = template
+            .transactions
+            .iter()
+            .map(|tx| deserialize::<Transaction>(&tx.raw_tx).unwrap());
+        let mut block = Block {
+            header: Header {
+                version: Version::from_consensus(template.version as i32),
+                prev_blockhash: template.previous_block_hash,
+                merkle_root: TxMerkleNode::all_zeros(),
+                time: template.current_time as u32,
+                bits: CompactTarget::from_consensus(u32::from_be_bytes(
+                    template.bits.clone().try_into().unwrap(),
+                )),
+                nonce: 0,
+            },
+            txdata: std::iter::once(coinbase).chain(transactions).collect(),
+        };
+        block.header.merkle_root = block.compute_merkle_root().unwrap();
+        assert!(template.min_time <= block.header.time as u64);
+        while block.header.validate_pow(block.header.target()).is_err() {
+            block.header.nonce += 1;
+        }
+
+        rpc.submit_block(&block).unwrap();
+        assert_eq!(rpc.get_best_block_hash().unwrap(), block.block_hash());
+        assert!(rpc.ledger.get_mempool_transactions().is_empty());
+
+        // Next template is built on top of the submitted block.
+        let template = rpc
+            .get_block_template(
+                json::GetBlockTemplateModes::Template,
+                &[json::GetBlockTemplateRules::SegWit],
+                &[],
+            )
+            .unwrap();
+        assert_eq!(template.previous_block_hash, block.block_hash());
+        assert!(template.transactions.is_empty());
+        assert_eq!(
+            template.coinbase_value,
+            rpc.ledger.get_block_subsidy(103).unwrap()
+        );
+    }
+
+    #[test]
+    fn get_block_template_with_send_to_address() {
+        let rpc = Client::new(
+            "memory://get_block_template_with_send_to_address",
+            bitcoincore_rpc::Auth::None,
+        )
+        .unwrap();
+        let address = Ledger::generate_credential_from_witness().address;
+        rpc.generate_to_address(1, &address).unwrap();
+
+        // Transaction of `send_to_address` spends an output that is unknown to
+        // the ledger, but it is mined like any other mempool transaction.
+        let txid = rpc
+            .send_to_address(
+                &address,
+                Amount::from_sat(0x45),
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+            )
+            .unwrap();
+
+        let template = rpc
+            .get_block_template(
+                json::GetBlockTemplateModes::Template,
+                &[json::GetBlockTemplateRules::SegWit],
+                &[],
+            )
+            .unwrap();
+        assert_eq!(template.transactions.len(), 1);
+        assert_eq!(template.transactions[0].txid, txid);
+
+        let coinbase = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                script_sig: Builder::new()
+                    .push_int(template.height as i64)
+                    .push_slice(b"pool")
+                    .into_script(),
+                witness: Witness::from_slice(&[[0u8; 32]]),
+                ..Default::default()
+            }],
+            output: vec![
+                TxOut {
+                    value: template.coinbase_value,
+                    script_pubkey: address.script_pubkey(),
+                },
+                TxOut {
+                    value: Amount::ZERO,
+                    script_pubkey: template.default_witness_commitment.clone(),
+                },
+            ],
+        };
+        let transactions = template
+            .transactions
+            .iter()
+            .map(|tx| deserialize::<Transaction>(&tx.raw_tx).unwrap());
+        let mut block = Block {
+            header: Header {
+                version: Version::from_consensus(template.version as i32),
+                prev_blockhash: template.previous_block_hash,
+                merkle_root: TxMerkleNode::all_zeros(),
+                time: template.current_time as u32,
+                bits: CompactTarget::from_consensus(u32::from_be_bytes(
+                    template.bits.clone().try_into().unwrap(),
+                )),
+                nonce: 0,
+            },
+            txdata: std::iter::once(coinbase).chain(transactions).collect(),
+        };
+        block.header.merkle_root = block.compute_merkle_root().unwrap();
+        while block.header.validate_pow(block.header.target()).is_err() {
+            block.header.nonce += 1;
+        }
+
+        rpc.submit_block(&block).unwrap();
+        assert_eq!(rpc.get_best_block_hash().unwrap(), block.block_hash());
+        assert_eq!(rpc.ledger.get_transaction_block_height(&txid).unwrap(), 2);
+        assert!(rpc.ledger.get_mempool_transactions().is_empty());
+    }
+
     #[test]
     fn fund_raw_transaction() {
         let rpc = Client::new("fund_raw_transaction", bitcoincore_rpc::Auth::None).unwrap();
@@ -2,7 +2,7 @@
 //!
 //! This crate provides address related ledger interfaces.
 
-use super::Ledger;
+use super::{errors::LedgerError, Ledger};
 use bitcoin::{
     opcodes::OP_TRUE,
     taproot::{LeafVersion, TaprootBuilder},
@@ -24,11 +24,19 @@ pub struct UserCredential {
 
 impl UserCredential {
     /// Creates a new `UserCredential` with random keys. Bitcoin address has the
-    /// type of "p2tr".
+    /// type of "p2tr" and it is for regtest.
     pub fn new() -> Self {
+        let (secret_key, _) = Secp256k1::new().generate_keypair(&mut rand::thread_rng());
+
+        Self::from_secret_key(secret_key, Network::Regtest)
+    }
+
+    /// Creates a new `UserCredential` with the given secret key. Bitcoin
+    /// address has the type of "p2tr" and it is for `network`.
+    pub fn from_secret_key(secret_key: SecretKey, network: Network) -> Self {
         let secp = Secp256k1::new();
 
-        let (secret_key, public_key) = secp.generate_keypair(&mut rand::thread_rng());
+        let public_key = PublicKey::from_secret_key(&secp, &secret_key);
         tracing::trace!(
             "New secret/public key pair: {:?} {:?}",
             secret_key,
@@ -39,7 +47,7 @@ impl UserCredential {
         let (x_only_public_key, _parity) = XOnlyPublicKey::from_keypair(&keypair);
         tracing::trace!("New x-only public key: {:?}", x_only_public_key);
 
-        let address = Address::p2tr(&secp, x_only_public_key, None, Network::Regtest);
+        let address = Address::p2tr(&secp, x_only_public_key, None, network);
         tracing::trace!("New bitcoin address: {:?}", address);
 
         Self {
@@ -81,6 +89,42 @@ impl Ledger {
 
         credential
     }
+    /// Creates Bitcoin credentials from a witness program, using the ledger's
+    /// randomness and network. Unlike
+    /// [`Ledger::generate_credential_from_witness`], this is deterministic for
+    /// a ledger with a seed.
+    pub fn create_credential(&self) -> Result<UserCredential, LedgerError> {
+        let network = self.get_network()?;
+
+        let secret_key = loop {
+            // Almost every 32 byte value is a valid secret key.
+            if let Ok(secret_key) = SecretKey::from_slice(&self.get_random_bytes()?) {
+                break secret_key;
+            }
+        };
+
+        let mut credential = UserCredential::from_secret_key(secret_key, network);
+
+        Ledger::create_witness(&mut credential);
+
+        credential.address =
+            Address::from_witness_program(credential.witness_program.unwrap(), network);
+
+        Ok(credential)
+    }
+    /// Checks if `address` is valid for the ledger's network.
+    pub fn check_address(&self, address: &Address) -> Result<(), LedgerError> {
+        let network = self.get_network()?;
+
+        if !address.as_unchecked().is_valid_for_network(network) {
+            return Err(LedgerError::Address(format!(
+                "Address {} is not valid for network {}",
+                address, network
+            )));
+        }
+
+        Ok(())
+    }
     /// Generates the constant Bitcoin credentials from a witness program.
     #[tracing::instrument]
     pub fn get_constant_credential_from_witness() -> UserCredential {
@@ -161,7 +205,7 @@ impl Ledger {
 
 #[cfg(test)]
 mod tests {
-    use crate::ledger::Ledger;
+    use crate::ledger::{errors::LedgerError, Ledger};
     use bitcoin::{key::TapTweak, AddressType};
 
     #[test]
@@ -185,4 +229,30 @@ mod tests {
                 .into()
         ));
     }
+
+    #[test]
+    fn create_credential() {
+        let ledger = Ledger::new("memory://create_credential?seed=1").unwrap();
+        let other = Ledger::new("memory://create_credential_other?seed=1").unwrap();
+
+        let credential = ledger.create_credential().unwrap();
+        assert_eq!(credential, other.create_credential().unwrap());
+        assert_ne!(credential, ledger.create_credential().unwrap());
+        assert!(credential.witness.is_some());
+    }
+
+    #[test]
+    fn network() {
+        let ledger = Ledger::new("memory://address_network?network=testnet").unwrap();
+
+        let credential = ledger.create_credential().unwrap();
+        assert!(credential.address.to_string().starts_with("tb1"));
+        ledger.check_address(&credential.address).unwrap();
+
+        let regtest_address = Ledger::generate_address_from_witness();
+        assert!(matches!(
+            ledger.check_address(&regtest_address),
+            Err(LedgerError::Address(_))
+        ));
+    }
 }
@@ -1,18 +1,35 @@
 //! # Block Related Ledger Operations
+//!
+//! Blocks have real proof of work. Every network uses regtest's proof of work
+//! limit as it's target, because mining with a real network's difficulty is not
+//! feasible for a mock.
 
 use super::errors::LedgerError;
-use super::Ledger;
+use super::{settings, transactions::CoinbaseOptions, Ledger};
 use crate::utils;
 use bitcoin::block::{Header, Version};
 use bitcoin::consensus::{Decodable, Encodable};
+use bitcoin::constants::genesis_block;
 use bitcoin::hashes::Hash;
-use bitcoin::{Address, Block, BlockHash, CompactTarget, Transaction, TxMerkleNode, Txid};
+use bitcoin::script::Builder;
+use bitcoin::{Address, Amount, Block, BlockHash, Network, Target, Transaction, Txid, Work};
 use rusqlite::params;
+use std::collections::HashSet;
+use std::ops::RangeInclusive;
 use std::str::FromStr;
-use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Start of a witness commitment output script: OP_RETURN, push of 36 bytes and
+/// the commitment header.
+const WITNESS_COMMITMENT_PREFIX: [u8; 6] = [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
 
 impl Ledger {
-    /// Mines current transactions that are in mempool to a block.
+    /// Mines current transactions that are in mempool to a block. Coinbase
+    /// transaction collects the block subsidy and the fees of the mined
+    /// transactions.
+    ///
+    /// Transactions are selected by their ancestor feerate, within the block
+    /// weight and sigop cost limits. Transactions that don't fit stay in the
+    /// mempool.
     ///
     /// # Parameters
     ///
@@ -23,81 +40,479 @@ impl Ledger {
     /// Will panic if there was a problem writing data to ledger.
     #[tracing::instrument(skip(self))]
     pub fn mine_block(&self, address: &Address) -> Result<BlockHash, LedgerError> {
-        let mut transactions = self.get_mempool_transactions();
+        self.mine_block_with_coinbase(address, &CoinbaseOptions::default())
+    }
+
+    /// Same as [`Ledger::mine_block`], but the coinbase transaction is
+    /// customized with `options`.
+    #[tracing::instrument(skip(self))]
+    pub fn mine_block_with_coinbase(
+        &self,
+        address: &Address,
+        options: &CoinbaseOptions,
+    ) -> Result<BlockHash, LedgerError> {
+        self.atomically(|| {
+            let mempool = self.get_mempool_transactions();
+            let transactions = self.select_block_transactions(&mempool)?;
+
+            self.mine_transactions(address, options, transactions, None)
+        })
+    }
+
+    /// Same as [`Ledger::mine_block`], but the block's time is `time`, in UNIX
+    /// format, instead of the ledger clock's. It must be greater than the
+    /// median time past of the tip and at most 2 hours ahead of the clock.
+    #[tracing::instrument(skip(self))]
+    pub fn mine_block_at(&self, address: &Address, time: u32) -> Result<BlockHash, LedgerError> {
+        self.atomically(|| {
+            let mempool = self.get_mempool_transactions();
+            let transactions = self.select_block_transactions(&mempool)?;
+
+            self.mine_transactions(
+                address,
+                &CoinbaseOptions::default(),
+                transactions,
+                Some(time),
+            )
+        })
+    }
+
+    /// Mines a block with exactly the given `transactions`, in given order.
+    /// They can be mempool transactions or new ones, which are verified before
+    /// getting mined. Other mempool transactions stay in the mempool, unless
+    /// they conflict with the block.
+    #[tracing::instrument(skip(self))]
+    pub fn mine_block_with_transactions(
+        &self,
+        address: &Address,
+        transactions: Vec<Transaction>,
+    ) -> Result<BlockHash, LedgerError> {
+        self.atomically(|| {
+            self.mine_transactions(address, &CoinbaseOptions::default(), transactions, None)
+        })
+    }
+
+    /// Mines `transactions` to a block on top of the active chain. Mempool
+    /// transactions that are not mined are re-added to the mempool after the
+    /// block, so that their heights point to the next block.
+    ///
+    /// Block's time is `time` if it is given. Otherwise, it is taken from the
+    /// ledger's clock.
+    fn mine_transactions(
+        &self,
+        address: &Address,
+        options: &CoinbaseOptions,
+        transactions: Vec<Transaction>,
+        time: Option<u32>,
+    ) -> Result<BlockHash, LedgerError> {
+        let left_out = self.add_block_transactions(&transactions)?;
+
+        let fees = transactions
+            .iter()
+            .map(|tx| self.calculate_transaction_fee(tx))
+            .sum();
         let coinbase_transaction = self.create_coinbase_transaction(
             address,
             transactions.iter().map(|tx| tx.compute_wtxid()).collect(),
+            self.get_block_height()? + 1,
+            fees,
+            options,
         )?;
-        transactions.insert(0, coinbase_transaction.clone());
+        let transactions: Vec<Transaction> = [coinbase_transaction.clone()]
+            .into_iter()
+            .chain(transactions)
+            .collect();
         tracing::debug!("Number of transactions in block: {}", transactions.len());
 
         self.add_transaction_unconditionally(coinbase_transaction)?;
 
-        let block = self.create_block(transactions)?;
+        let block = match time {
+            Some(time) => {
+                let tip = self.get_block_hash(self.get_block_height()?)?;
+                self.build_block(tip, time, transactions)?
+            }
+            None => self.create_block(transactions)?,
+        };
 
         self.clean_mempool();
-        self.add_block(block)
+        let block_hash = self.add_block(block)?;
+
+        self.attach_mempool(left_out, HashSet::new(), false)?;
+
+        Ok(block_hash)
     }
 
-    /// Creates a block using given transactions.
-    pub fn create_block(&self, transactions: Vec<Transaction>) -> Result<Block, LedgerError> {
-        let prev_block_height = self.get_block_height()?;
-        let prev_block_time = self.get_block_time(prev_block_height)?;
+    /// Validates a block that is built outside of the ledger and adds it on
+    /// top of the active chain. Block must extend the tip, have a valid merkle
+    /// root and witness commitment and start with a coinbase transaction,
+    /// which begins with the block height. Transactions that are not in the
+    /// mempool are verified, like the ones in a mined block. Proof of work,
+    /// limits and coinbase value are checked while adding the block.
+    ///
+    /// Mempool transactions that are not in the block stay in the mempool,
+    /// unless they conflict with the block.
+    #[tracing::instrument(skip_all)]
+    pub fn submit_block(&self, block: Block) -> Result<BlockHash, LedgerError> {
+        let block_hash = block.block_hash();
+        let height = self.get_block_height()? + 1;
 
-        let prev_blockhash = match self.get_block_with_height(prev_block_height) {
-            Ok(b) => b.block_hash(),
-            Err(e) => {
-                if prev_block_height >= 1 {
+        if self.get_block_with_hash(block_hash).is_ok() {
+            return Err(LedgerError::Block(format!(
+                "Block {} is already in the ledger",
+                block_hash
+            )));
+        }
+
+        let tip = self.get_block_hash(height - 1)?;
+        if block.header.prev_blockhash != tip {
+            return Err(LedgerError::Block(format!(
+                "Block {} doesn't extend the tip {} of the active chain",
+                block_hash, tip
+            )));
+        }
+
+        let merkle_root = utils::calculate_merkle_root(
+            block.txdata.iter().map(|tx| tx.compute_txid()).collect(),
+        )?;
+        if merkle_root != block.header.merkle_root {
+            return Err(LedgerError::Block(format!(
+                "Block {} has merkle root {}, but it's transactions' merkle root is {}",
+                block_hash, block.header.merkle_root, merkle_root
+            )));
+        }
+
+        let Some((coinbase, transactions)) = block.txdata.split_first() else {
+            return Err(LedgerError::Block(format!(
+                "Block {} doesn't have any transactions",
+                block_hash
+            )));
+        };
+        if !coinbase.is_coinbase() || transactions.iter().any(|tx| tx.is_coinbase()) {
+            return Err(LedgerError::Block(format!(
+                "Block {} must have a coinbase transaction as it's first and only first transaction",
+                block_hash
+            )));
+        }
+
+        let height_script = Builder::new().push_int(height as i64).into_script();
+        if !coinbase.input[0]
+            .script_sig
+            .as_bytes()
+            .starts_with(height_script.as_bytes())
+        {
+            return Err(LedgerError::Block(format!(
+                "Coinbase of block {} doesn't start with the block height {}",
+                block_hash, height
+            )));
+        }
+
+        self.check_witness_commitment(&block)?;
+
+        self.atomically(|| {
+            let left_out = self.add_block_transactions(transactions)?;
+            self.add_transaction_unconditionally(coinbase.clone())?;
+
+            self.clean_mempool();
+            self.add_block(block.clone())?;
+
+            self.attach_mempool(left_out, HashSet::new(), false)?;
+
+            Ok(block_hash)
+        })
+    }
+
+    /// Checks the witness commitment in the coinbase of `block`. Blocks without
+    /// any witness data don't need a commitment.
+    pub fn check_witness_commitment(&self, block: &Block) -> Result<(), LedgerError> {
+        let coinbase = &block.txdata[0];
+        let commitment = coinbase.output.iter().rev().find(|output| {
+            output
+                .script_pubkey
+                .as_bytes()
+                .starts_with(&WITNESS_COMMITMENT_PREFIX)
+                && output.script_pubkey.len() >= 38
+        });
+
+        let Some(commitment) = commitment else {
+            if block
+                .txdata
+                .iter()
+                .any(|tx| tx.input.iter().any(|input| !input.witness.is_empty()))
+            {
+                return Err(LedgerError::Block(format!(
+                    "Block {} has witness data but no witness commitment",
+                    block.block_hash()
+                )));
+            }
+
+            return Ok(());
+        };
+
+        let witness_reserved_value: [u8; 32] =
+            match coinbase.input[0].witness.iter().collect::<Vec<_>>()[..] {
+                [value] => match value.try_into() {
+                    Ok(value) => value,
+                    Err(_) => {
+                        return Err(LedgerError::Block(format!(
+                            "Coinbase of block {} has an invalid witness reserved value",
+                            block.block_hash()
+                        )))
+                    }
+                },
+                _ => {
+                    return Err(LedgerError::Block(format!(
+                        "Coinbase of block {} must have a single witness reserved value",
+                        block.block_hash()
+                    )))
+                }
+            };
+
+        let expected = Ledger::create_witness_commitment_script(
+            block
+                .txdata
+                .iter()
+                .skip(1)
+                .map(|tx| tx.compute_wtxid())
+                .collect(),
+            witness_reserved_value,
+        )?;
+        if commitment.script_pubkey.as_bytes()[..38] != expected.as_bytes()[..] {
+            return Err(LedgerError::Block(format!(
+                "Block {} has an invalid witness commitment",
+                block.block_hash()
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Detaches the mempool and adds `transactions` to the ledger for the next
+    /// block, in given order. New transactions are verified first. Mempool
+    /// transactions are already accepted by the ledger, so they are re-added
+    /// as they are, no matter if the block is mined or submitted. Returns the
+    /// mempool transactions that are not in `transactions`, which should be
+    /// re-attached after the block.
+    fn add_block_transactions(
+        &self,
+        transactions: &[Transaction],
+    ) -> Result<Vec<Transaction>, LedgerError> {
+        let mempool = self.detach_mempool()?;
+        let mempool_txids: HashSet<Txid> = mempool.iter().map(|tx| tx.compute_txid()).collect();
+        let txids: HashSet<Txid> = transactions.iter().map(|tx| tx.compute_txid()).collect();
+
+        // Transactions must come after the unconfirmed transactions they spend
+        // from.
+        let mut mined: HashSet<Txid> = HashSet::new();
+        for transaction in transactions {
+            let txid = transaction.compute_txid();
+
+            for input in &transaction.input {
+                let parent = input.previous_output.txid;
+
+                if (txids.contains(&parent) || mempool_txids.contains(&parent))
+                    && !mined.contains(&parent)
+                {
                     return Err(LedgerError::Block(format!(
-                        "Couldn't get previous block hash with height {}: {}",
-                        prev_block_height, e
+                        "Transaction {} spends from unconfirmed transaction {}, which doesn't come before it in the block",
+                        txid, parent
                     )));
                 }
+            }
 
-                BlockHash::all_zeros()
+            // Same order as `check_transaction`, so that a transaction gets the
+            // same error in a block and in the mempool. Inputs of the mempool
+            // transactions are already checked.
+            let is_in_mempool = mempool_txids.contains(&txid);
+            if !is_in_mempool {
+                self.check_transaction_inputs(transaction)?;
             }
+            self.check_transaction_finality(transaction)?;
+            self.check_sequence_locks(transaction)?;
+
+            if !mined.insert(txid) {
+                return Err(LedgerError::Block(format!(
+                    "Transaction {} is in the block more than once",
+                    txid
+                )));
+            }
+
+            if is_in_mempool {
+                self.reattach_transaction(transaction)?;
+            } else {
+                self.add_transaction(transaction.clone())?;
+            }
+        }
+
+        Ok(mempool
+            .into_iter()
+            .filter(|tx| !mined.contains(&tx.compute_txid()))
+            .collect())
+    }
+
+    /// Returns the block subsidy for the block at `height`. Subsidy starts at
+    /// 50 BTC and it is halved every 150 blocks on regtest and every 210000
+    /// blocks on other networks.
+    pub fn get_block_subsidy(&self, height: u32) -> Result<Amount, LedgerError> {
+        let halving_interval = match self.get_network()? {
+            Network::Regtest => 150,
+            _ => 210_000,
         };
 
+        let halvings = height / halving_interval;
+        if halvings >= 64 {
+            return Ok(Amount::ZERO);
+        }
+
+        Ok(Amount::from_sat(utils::INITIAL_BLOCK_SUBSIDY >> halvings))
+    }
+
+    /// Checks if coinbase of `block` at `height` claims at most the block
+    /// subsidy and the fees of the other transactions in the block.
+    pub fn check_block_reward(&self, block: &Block, height: u32) -> Result<(), LedgerError> {
+        let Some(coinbase) = block.txdata.first() else {
+            return Err(LedgerError::Block(format!(
+                "Block {} doesn't have a coinbase transaction",
+                block.block_hash()
+            )));
+        };
+
+        let fees: Amount = block
+            .txdata
+            .iter()
+            .skip(1)
+            .map(|tx| self.calculate_transaction_fee(tx))
+            .sum();
+        let allowed = self.get_block_subsidy(height)? + fees;
+        let claimed = self.calculate_transaction_output_value(coinbase);
+
+        if claimed > allowed {
+            return Err(LedgerError::Block(format!(
+                "Coinbase of block {} claims {}, which is more than the allowed {}",
+                block.block_hash(),
+                claimed,
+                allowed
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Creates a block using given transactions.
+    pub fn create_block(&self, transactions: Vec<Transaction>) -> Result<Block, LedgerError> {
+        let prev_block_height = self.get_block_height()?;
+        let time = self.get_block_time(prev_block_height + 1)?;
+        let prev_blockhash = self.get_block_hash(prev_block_height)?;
+
+        self.build_block(prev_blockhash, time, transactions)
+    }
+
+    /// Builds a block on top of the block with `prev_blockhash`, using given
+    /// time and transactions.
+    ///
+    /// Nonce is incremented until block hash meets the target and it is unique
+    /// in the ledger. This way, blocks with the same contents can be in
+    /// different branches.
+    pub fn build_block(
+        &self,
+        prev_blockhash: BlockHash,
+        time: u32,
+        transactions: Vec<Transaction>,
+    ) -> Result<Block, LedgerError> {
         tracing::trace!("Transactions in block: {:?}", transactions);
         let txids: Vec<Txid> = transactions.iter().map(|tx| tx.compute_txid()).collect();
         tracing::trace!("TxIds in block: {:?}", txids);
         let merkle_root = utils::calculate_merkle_root(txids)?;
         tracing::trace!("Merkle root of the TxIds: {:?}", merkle_root);
 
-        Ok(Block {
+        let target = Ledger::get_block_target();
+        let mut block = Block {
             header: Header {
-                version: Version::TWO,
+                version: Ledger::get_block_version(),
                 prev_blockhash,
                 merkle_root,
-                time: prev_block_time + (10 * 60),
-                bits: CompactTarget::from_consensus(0x20FFFFFF),
+                time,
+                bits: target.to_compact_lossy(),
                 nonce: 0,
             },
             txdata: transactions,
-        })
+        };
+
+        while block.header.validate_pow(target).is_err()
+            || self.get_block_with_hash(block.block_hash()).is_ok()
+        {
+            block.header.nonce = match block.header.nonce.checked_add(1) {
+                Some(nonce) => nonce,
+                None => {
+                    return Err(LedgerError::Block(format!(
+                        "Couldn't find a valid nonce for block {:?}",
+                        block.header
+                    )))
+                }
+            };
+        }
+        tracing::trace!("Found nonce {} for block", block.header.nonce);
+
+        Ok(block)
+    }
+
+    /// Returns the version of the blocks that are built by the ledger.
+    pub fn get_block_version() -> Version {
+        Version::TWO
     }
 
-    /// Adds a block to ledger.
+    /// Returns the proof of work target of the blocks.
+    pub fn get_block_target() -> Target {
+        Target::MAX_ATTAINABLE_REGTEST
+    }
+
+    /// Checks if `header` has the expected target and it's hash meets that
+    /// target.
+    pub fn check_block_header(header: &Header) -> Result<(), LedgerError> {
+        if let Err(e) = header.validate_pow(Ledger::get_block_target()) {
+            return Err(LedgerError::Block(format!(
+                "Block {} has invalid proof of work: {}",
+                header.block_hash(),
+                e
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Adds a block to ledger, on top of the active chain. Block's time is
+    /// taken from it's header.
     ///
-    /// Uses current block height and time to calculate next block height and
-    /// time. Previous height + 1 is used for height while previous time + 10
-    /// minutes is used for time.
+    /// Block is rejected if it's proof of work is invalid, it's time is not
+    /// greater than the median time past or too far in the future, it exceeds
+    /// the weight or sigop cost limit or it's coinbase claims more than
+    /// allowed.
     ///
     /// # Panics
     ///
     /// Will panic if there was a problem writing data to ledger.
     fn add_block(&self, block: Block) -> Result<BlockHash, LedgerError> {
-        let prev_block_height = self.get_block_height()?;
-        let prev_block_time = self.get_block_time(prev_block_height)?;
+        Ledger::check_block_header(&block.header)?;
+        self.check_block_time(&block.header)?;
+        self.check_block_limits(&block)?;
+
+        let current_block_height = self.get_block_height()? + 1;
+        self.check_block_reward(&block, current_block_height)?;
 
-        let current_block_height = prev_block_height + 1;
-        let current_block_time = prev_block_time + (10 * 60);
+        let current_block_time = block.header.time;
         tracing::debug!(
             "New block's height: {}, time: {}",
             current_block_height,
             current_block_time
         );
 
+        self.insert_block(&block, current_block_height, current_block_time)?;
+
+        Ok(block.block_hash())
+    }
+
+    /// Inserts a block to the active chain with given height and time, alongside
+    /// with it's undo data.
+    pub fn insert_block(&self, block: &Block, height: u32, time: u32) -> Result<(), LedgerError> {
         let mut hash: Vec<u8> = Vec::new();
         let block_hash = block.block_hash();
         block_hash.consensus_encode(&mut hash).unwrap();
@@ -113,13 +528,7 @@ impl Ledger {
 
         if let Err(e) = self.database.lock().unwrap().execute(
             "INSERT INTO blocks (height, time, hash, coinbase, body) VALUES (?1, ?2, ?3, ?4, ?5)",
-            params![
-                current_block_height,
-                current_block_time,
-                hash,
-                coinbase_txid,
-                body
-            ],
+            params![height, time, hash, coinbase_txid, body],
         ) {
             return Err(LedgerError::Block(format!(
                 "Couldn't add block {:?} to ledger: {}",
@@ -127,14 +536,18 @@ impl Ledger {
             )));
         };
 
-        Ok(block.block_hash())
+        // Block might be in the stale blocks, if it is reconnected.
+        self.remove_stale_block(block_hash)?;
+
+        self.add_undo_data(height, block)
     }
+
     /// Returns a block with `height` from ledger.
     pub fn get_block_with_height(&self, height: u32) -> Result<Block, LedgerError> {
         let body = match self.database.lock().unwrap().query_row(
             "SELECT body FROM blocks WHERE height = ?1",
             params![height],
-            |row| Ok(row.get::<_, Vec<u8>>(0)),
+            |row| row.get::<_, Vec<u8>>(0),
         ) {
             Ok(qr) => qr,
             Err(e) => {
@@ -144,8 +557,6 @@ impl Ledger {
                 )))
             }
         };
-        // Genesis block will also return a database error. Ignore that.
-        let body = body.unwrap_or_default();
 
         match Block::consensus_decode(&mut body.as_slice()) {
             Ok(block) => Ok(block),
@@ -160,23 +571,11 @@ impl Ledger {
         let mut encoded_hash: Vec<u8> = Vec::new();
         hash.consensus_encode(&mut encoded_hash).unwrap();
 
-        // Handle genesis block.
-        if hash == BlockHash::all_zeros() {
-            return Ok(Block {
-                header: Header {
-                    version: Version::TWO,
-                    prev_blockhash: BlockHash::all_zeros(),
-                    merkle_root: TxMerkleNode::all_zeros(),
-                    time: 0,
-                    bits: CompactTarget::default(),
-                    nonce: 0,
-                },
-                txdata: vec![],
-            });
-        }
-
+        // Blocks that aren't in the active chain are also searched.
         let qr = match self.database.lock().unwrap().query_row(
-            "SELECT body FROM blocks WHERE hash = ?1",
+            "SELECT body FROM blocks WHERE hash = ?1
+            UNION ALL
+            SELECT body FROM stale_blocks WHERE hash = ?1",
             params![encoded_hash],
             |row| Ok(row.get::<_, Vec<u8>>(0).unwrap()),
         ) {
@@ -198,6 +597,244 @@ impl Ledger {
         }
     }
 
+    /// Returns hash of the block with `height` in the active chain.
+    pub fn get_block_hash(&self, height: u32) -> Result<BlockHash, LedgerError> {
+        Ok(self.get_block_with_height(height)?.block_hash())
+    }
+
+    /// Returns height of the block with `hash`, if it is in the active chain.
+    ///
+    /// In ledgers with the `legacy_genesis` setting, all zero hash refers to
+    /// the genesis block.
+    pub fn get_block_height_with_hash(&self, hash: BlockHash) -> Option<u32> {
+        if hash == BlockHash::all_zeros() && self.has_legacy_genesis() {
+            return Some(0);
+        }
+
+        let mut encoded_hash: Vec<u8> = Vec::new();
+        hash.consensus_encode(&mut encoded_hash).unwrap();
+
+        self.database
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT height FROM blocks WHERE hash = ?1",
+                params![encoded_hash],
+                |row| row.get::<_, u32>(0),
+            )
+            .ok()
+    }
+
+    /// Returns hash of the block before the block with `header`. Blocks that
+    /// point to the placeholder genesis block of a ledger with the
+    /// `legacy_genesis` setting get the hash of the real genesis block.
+    pub fn get_previous_block_hash(&self, header: &Header) -> Result<BlockHash, LedgerError> {
+        if header.prev_blockhash == BlockHash::all_zeros() && self.has_legacy_genesis() {
+            return self.get_block_hash(0);
+        }
+
+        Ok(header.prev_blockhash)
+    }
+
+    /// Returns if the ledger is migrated from a database with blocks on top of
+    /// the placeholder genesis block.
+    fn has_legacy_genesis(&self) -> bool {
+        matches!(self.get_setting(settings::LEGACY_GENESIS), Ok(Some(_)))
+    }
+
+    /// Makes sure that the active chain starts with the genesis block of the
+    /// ledger's network. Genesis block is replaced if the network is changed
+    /// before any block is mined.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the active chain has blocks on top of another
+    /// network's genesis block.
+    pub fn check_genesis_block(&self) -> Result<(), LedgerError> {
+        let network = self.get_network()?;
+        let genesis = genesis_block(network);
+        let block_hash = genesis.block_hash();
+
+        if self.get_block_hash(0)? == block_hash {
+            return Ok(());
+        }
+        if self.get_block_height()? != 0 {
+            return Err(LedgerError::Block(format!(
+                "Ledger's chain doesn't start with the genesis block of network {}",
+                network
+            )));
+        }
+
+        let mut hash: Vec<u8> = Vec::new();
+        block_hash.consensus_encode(&mut hash).unwrap();
+        let mut body: Vec<u8> = Vec::new();
+        genesis.consensus_encode(&mut body).unwrap();
+
+        if let Err(e) = self.database.lock().unwrap().execute(
+            "UPDATE blocks SET time = ?1, hash = ?2, coinbase = ?3, body = ?4 WHERE height = 0",
+            params![
+                genesis.header.time,
+                hash,
+                genesis.txdata[0].compute_txid().to_string(),
+                body
+            ],
+        ) {
+            return Err(LedgerError::Block(format!(
+                "Couldn't set genesis block to {}: {}",
+                block_hash, e
+            )));
+        };
+        tracing::debug!("Genesis block is set to {block_hash}");
+
+        Ok(())
+    }
+
+    /// Returns the number of confirmations of the block with `hash`. Blocks
+    /// that are not in the active chain have -1 confirmations.
+    pub fn get_block_confirmations(&self, hash: BlockHash) -> Result<i32, LedgerError> {
+        match self.get_block_height_with_hash(hash) {
+            Some(height) => Ok((self.get_block_height()? - height + 1) as i32),
+            None => {
+                self.get_block_with_hash(hash)?;
+
+                Ok(-1)
+            }
+        }
+    }
+
+    /// Returns hash of the block that comes after the block with `hash` in the
+    /// active chain, if there is one.
+    pub fn get_next_block_hash(&self, hash: BlockHash) -> Result<Option<BlockHash>, LedgerError> {
+        let Some(height) = self.get_block_height_with_hash(hash) else {
+            return Ok(None);
+        };
+
+        if height == self.get_block_height()? {
+            return Ok(None);
+        }
+
+        Ok(Some(self.get_block_hash(height + 1)?))
+    }
+
+    /// Returns header of the block with `hash`, which can be in either the
+    /// active chain or a stale branch. Only the header is read from the
+    /// ledger, not the whole block.
+    pub fn get_block_header(&self, hash: BlockHash) -> Result<Header, LedgerError> {
+        let mut encoded_hash: Vec<u8> = Vec::new();
+        hash.consensus_encode(&mut encoded_hash).unwrap();
+
+        let qr = match self.database.lock().unwrap().query_row(
+            "SELECT substr(body, 1, 80) FROM blocks WHERE hash = ?1
+            UNION ALL
+            SELECT substr(body, 1, 80) FROM stale_blocks WHERE hash = ?1",
+            params![encoded_hash],
+            |row| row.get::<_, Vec<u8>>(0),
+        ) {
+            Ok(qr) => qr,
+            Err(e) => {
+                return Err(LedgerError::Block(format!(
+                    "Couldn't find any block with block hash {}: {}",
+                    hash, e
+                )))
+            }
+        };
+
+        match Header::consensus_decode(&mut qr.as_slice()) {
+            Ok(header) => Ok(header),
+            Err(e) => Err(LedgerError::Block(format!(
+                "Internal error while reading block header from ledger: {}",
+                e
+            ))),
+        }
+    }
+
+    /// Returns headers of the blocks in the active chain with `heights`, in
+    /// ascending order.
+    pub(super) fn get_block_headers(
+        &self,
+        heights: RangeInclusive<u32>,
+    ) -> Result<Vec<Header>, LedgerError> {
+        let database = self.database.lock().unwrap();
+
+        let mut stmt = database
+            .prepare(
+                "SELECT substr(body, 1, 80) FROM blocks WHERE height BETWEEN ?1 AND ?2 ORDER BY height",
+            )
+            .unwrap();
+        let headers = stmt
+            .query_map(params![*heights.start(), *heights.end()], |row| {
+                row.get::<_, Vec<u8>>(0)
+            })
+            .and_then(|rows| rows.collect::<Result<Vec<Vec<u8>>, rusqlite::Error>>());
+        let headers = match headers {
+            Ok(headers) => headers,
+            Err(e) => {
+                return Err(LedgerError::Block(format!(
+                    "Couldn't read block headers from ledger: {}",
+                    e
+                )))
+            }
+        };
+
+        headers
+            .iter()
+            .map(|header| {
+                Header::consensus_decode(&mut header.as_slice()).map_err(|e| {
+                    LedgerError::Block(format!(
+                        "Internal error while reading block header from ledger: {}",
+                        e
+                    ))
+                })
+            })
+            .collect()
+    }
+
+    /// Returns total work of the chain that ends with the block with `hash`,
+    /// including the genesis block.
+    pub fn get_chain_work(&self, hash: BlockHash) -> Result<Work, LedgerError> {
+        let mut work = Work::from_be_bytes([0; 32]);
+        let mut hash = hash;
+
+        // Blocks of a stale branch are added until the fork point. Rest of the
+        // chain is read from the active chain at once.
+        let fork_height = loop {
+            if let Some(height) = self.get_block_height_with_hash(hash) {
+                break height;
+            }
+
+            let header = self.get_block_header(hash)?;
+            work = work + header.work();
+            hash = header.prev_blockhash;
+        };
+
+        Ok(self
+            .get_block_headers(0..=fork_height)?
+            .iter()
+            .fold(work, |work, header| work + header.work()))
+    }
+
+    /// Returns median time past of the block with `hash`, which is the median
+    /// of the times of that block and it's 10 ancestors. Only those 11
+    /// headers are read.
+    pub fn get_median_time_past(&self, hash: BlockHash) -> Result<u32, LedgerError> {
+        let mut times = Vec::with_capacity(11);
+        let mut hash = hash;
+
+        while times.len() < 11 {
+            let header = self.get_block_header(hash)?;
+            times.push(header.time);
+
+            if header.prev_blockhash == BlockHash::all_zeros() {
+                break;
+            }
+            hash = header.prev_blockhash;
+        }
+
+        times.sort_unstable();
+
+        Ok(times[times.len() / 2])
+    }
+
     /// Returns current block height.
     ///
     /// # Panics
@@ -246,7 +883,9 @@ impl Ledger {
         // call.
         let txids: Vec<Txid> = {
             let db = self.database.lock().unwrap();
-            let mut stmt = db.prepare("SELECT (txid) FROM mempool").unwrap();
+            let mut stmt = db
+                .prepare("SELECT (txid) FROM mempool ORDER BY rowid")
+                .unwrap();
             let tx_iter = stmt
                 .query_map([], |row| {
                     let body: String = row.get(0).unwrap();
@@ -289,22 +928,15 @@ impl Ledger {
             .unwrap();
     }
 
-    /// Gets `block_height`'th block time, in UNIX format.
+    /// Gets `block_height`'th block time, in UNIX format. If `block_height` is
+    /// the next block's height, returns the time it would be mined with.
     ///
     /// # Panics
     ///
     /// Will panic if there is a problem with database.
     pub fn get_block_time(&self, block_height: u32) -> Result<u32, LedgerError> {
-        // Use current time for genesis block.
-        if block_height == 1 {
-            return Ok(SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_secs() as u32);
-        }
-
         if self.get_block_height()? + 1 == block_height {
-            return Ok(self.get_block_time(block_height - 1)? + (10 * 60));
+            return self.get_next_block_time(self.get_block_hash(block_height - 1)?);
         }
 
         match self.database.lock().unwrap().query_row(
@@ -324,14 +956,16 @@ impl Ledger {
 #[cfg(test)]
 mod tests {
     use crate::{
-        ledger::{self, Ledger},
-        utils::BLOCK_REWARD,
+        ledger::{self, CoinbaseOptions, Ledger},
+        utils::INITIAL_BLOCK_SUBSIDY,
+    };
+    use bitcoin::{
+        constants::genesis_block, Amount, Network, OutPoint, ScriptBuf, Target, Transaction, Txid,
     };
-    use bitcoin::{Amount, OutPoint, ScriptBuf, Transaction, Txid};
 
     #[test]
     fn mine_blocks_and_mempool() {
-        let ledger = Ledger::new("mine_blocks_and_mempool");
+        let ledger = Ledger::new("mine_blocks_and_mempool").unwrap();
         let address = ledger::Ledger::generate_credential_from_witness().address;
 
         let current_height = ledger.get_block_height().unwrap();
@@ -355,9 +989,188 @@ mod tests {
         assert!(ledger.get_mempool_transaction(tx.compute_txid()).is_none());
     }
 
+    #[test]
+    fn genesis_block_of_network() {
+        let ledger = Ledger::new("memory://genesis_block_of_network").unwrap();
+        let address = ledger::Ledger::generate_credential_from_witness().address;
+
+        let genesis = genesis_block(Network::Regtest);
+        assert_eq!(ledger.get_block_hash(0).unwrap(), genesis.block_hash());
+        assert_eq!(ledger.get_block_with_height(0).unwrap(), genesis);
+        assert_eq!(
+            ledger.get_block_with_hash(genesis.block_hash()).unwrap(),
+            genesis
+        );
+        assert_eq!(
+            ledger.get_block_height_with_hash(genesis.block_hash()),
+            Some(0)
+        );
+
+        let block_hash = ledger.mine_block(&address).unwrap();
+        let block = ledger.get_block_with_hash(block_hash).unwrap();
+        assert_eq!(block.header.prev_blockhash, genesis.block_hash());
+
+        // Network can't be changed after blocks are mined.
+        assert!(Ledger::new("memory://genesis_block_of_network?network=testnet").is_err());
+        assert_eq!(ledger.get_network().unwrap(), Network::Regtest);
+
+        let ledger =
+            Ledger::new("memory://genesis_block_of_network_signet?network=signet").unwrap();
+        assert_eq!(
+            ledger.get_block_hash(0).unwrap(),
+            genesis_block(Network::Signet).block_hash()
+        );
+    }
+
+    #[test]
+    fn proof_of_work() {
+        let ledger = Ledger::new("memory://proof_of_work").unwrap();
+        let address = ledger::Ledger::generate_credential_from_witness().address;
+
+        let block_hash = ledger.mine_block(&address).unwrap();
+        let header = ledger.get_block_with_hash(block_hash).unwrap().header;
+        assert_eq!(
+            header.validate_pow(Target::MAX_ATTAINABLE_REGTEST),
+            Ok(block_hash)
+        );
+
+        let mut block = ledger.create_block(vec![]).unwrap();
+        while block
+            .header
+            .validate_pow(Ledger::get_block_target())
+            .is_ok()
+        {
+            block.header.nonce += 1;
+        }
+        assert!(ledger.add_block(block).is_err());
+        assert_eq!(ledger.get_block_height().unwrap(), 1);
+    }
+
+    #[test]
+    fn chain_work_and_median_time_past() {
+        let ledger =
+            Ledger::new("memory://chain_work_and_median_time_past?time=1700000000").unwrap();
+        let address = ledger::Ledger::generate_credential_from_witness().address;
+
+        let genesis = ledger.get_block_hash(0).unwrap();
+        let genesis_header = ledger.get_block_with_hash(genesis).unwrap().header;
+        assert_eq!(
+            ledger.get_chain_work(genesis).unwrap(),
+            genesis_header.work()
+        );
+        assert_eq!(
+            ledger.get_median_time_past(genesis).unwrap(),
+            genesis_header.time
+        );
+
+        let mut hashes = vec![];
+        for _ in 0..20 {
+            hashes.push(ledger.mine_block(&address).unwrap());
+        }
+
+        let block_work = ledger.get_block_with_hash(hashes[0]).unwrap().header.work();
+        assert_eq!(
+            ledger.get_chain_work(hashes[19]).unwrap(),
+            (0..20).fold(genesis_header.work(), |work, _| work + block_work)
+        );
+
+        // Work of a stale branch is added to the work of it's fork point.
+        let stale = ledger.mine_block_on(hashes[9], &address).unwrap();
+        assert_eq!(
+            ledger.get_block_header(stale).unwrap(),
+            ledger.get_block_with_hash(stale).unwrap().header
+        );
+        assert_eq!(
+            ledger.get_chain_work(stale).unwrap(),
+            (0..11).fold(genesis_header.work(), |work, _| work + block_work)
+        );
+
+        // Block times are 1700000000, 1700000600, ... Median of the blocks 10
+        // to 20 is block 15's time.
+        assert_eq!(
+            ledger.get_median_time_past(hashes[19]).unwrap(),
+            1700000000 + 14 * 600
+        );
+        // Genesis block is older than the first block.
+        assert_eq!(ledger.get_median_time_past(hashes[1]).unwrap(), 1700000000);
+
+        assert_eq!(ledger.get_block_confirmations(hashes[19]).unwrap(), 1);
+        assert_eq!(ledger.get_block_confirmations(genesis).unwrap(), 21);
+        assert_eq!(
+            ledger.get_next_block_hash(genesis).unwrap(),
+            Some(hashes[0])
+        );
+        assert_eq!(ledger.get_next_block_hash(hashes[19]).unwrap(), None);
+
+        ledger.invalidate_block(hashes[19]).unwrap();
+        assert_eq!(ledger.get_block_confirmations(hashes[19]).unwrap(), -1);
+        assert_eq!(ledger.get_next_block_hash(hashes[18]).unwrap(), None);
+    }
+
+    #[test]
+    fn block_subsidy() {
+        let ledger = Ledger::new("memory://block_subsidy").unwrap();
+
+        let subsidy = Amount::from_sat(INITIAL_BLOCK_SUBSIDY);
+        assert_eq!(ledger.get_block_subsidy(1).unwrap(), subsidy);
+        assert_eq!(ledger.get_block_subsidy(149).unwrap(), subsidy);
+        assert_eq!(ledger.get_block_subsidy(150).unwrap(), subsidy / 2);
+        assert_eq!(ledger.get_block_subsidy(300).unwrap(), subsidy / 4);
+        assert_eq!(ledger.get_block_subsidy(150 * 64).unwrap(), Amount::ZERO);
+
+        let ledger = Ledger::new("memory://block_subsidy_testnet?network=testnet").unwrap();
+        assert_eq!(ledger.get_block_subsidy(150).unwrap(), subsidy);
+        assert_eq!(ledger.get_block_subsidy(210_000).unwrap(), subsidy / 2);
+    }
+
+    #[test]
+    fn coinbase_collects_fees() {
+        let ledger = Ledger::new("memory://coinbase_collects_fees").unwrap();
+        let address = ledger::Ledger::generate_credential_from_witness().address;
+
+        ledger.mine_block(&address).unwrap();
+        let coinbase = ledger.get_block_with_height(1).unwrap().txdata[0].clone();
+
+        let fee = Amount::from_sat(0x45);
+        let txout = ledger.create_txout(coinbase.output[0].value - fee, address.script_pubkey());
+        let tx = ledger.create_transaction(
+            vec![ledger.create_txin(coinbase.compute_txid(), 0)],
+            vec![txout],
+        );
+        ledger.add_transaction_unconditionally(tx.clone()).unwrap();
+        assert_eq!(ledger.calculate_transaction_fee(&tx), fee);
+
+        ledger.mine_block(&address).unwrap();
+        let coinbase = ledger.get_block_with_height(2).unwrap().txdata[0].clone();
+        assert_eq!(
+            coinbase.output[0].value,
+            Amount::from_sat(INITIAL_BLOCK_SUBSIDY) + fee
+        );
+    }
+
+    #[test]
+    fn reject_excessive_coinbase() {
+        let ledger = Ledger::new("memory://reject_excessive_coinbase").unwrap();
+        let address = ledger::Ledger::generate_credential_from_witness().address;
+
+        let coinbase = ledger
+            .create_coinbase_transaction(
+                &address,
+                vec![],
+                1,
+                Amount::from_sat(1),
+                &CoinbaseOptions::default(),
+            )
+            .unwrap();
+        let block = ledger.create_block(vec![coinbase]).unwrap();
+
+        assert!(ledger.add_block(block).is_err());
+        assert_eq!(ledger.get_block_height().unwrap(), 0);
+    }
+
     #[test]
     fn mine_and_check_coinbase_transaction() {
-        let ledger = Ledger::new("mine_and_check_coinbase_transaction");
+        let ledger = Ledger::new("mine_and_check_coinbase_transaction").unwrap();
         let address = ledger::Ledger::generate_credential_from_witness().address;
 
         ledger.mine_block(&address).unwrap();
@@ -376,13 +1189,13 @@ mod tests {
         );
         assert_eq!(
             coinbase_tx.output.first().unwrap().value,
-            Amount::from_sat(BLOCK_REWARD)
+            Amount::from_sat(INITIAL_BLOCK_SUBSIDY)
         );
     }
 
     #[test]
     fn create_add_get_block_with_height() {
-        let ledger = Ledger::new("create_add_get_block_with_height");
+        let ledger = Ledger::new("create_add_get_block_with_height").unwrap();
         let address = ledger::Ledger::generate_credential_from_witness().address;
 
         ledger.mine_block(&address).unwrap();
@@ -408,7 +1221,7 @@ mod tests {
 
     #[test]
     fn create_add_get_block_with_hash() {
-        let ledger = Ledger::new("create_add_get_block_with_hash");
+        let ledger = Ledger::new("create_add_get_block_with_hash").unwrap();
         let address = ledger::Ledger::generate_credential_from_witness().address;
         ledger.mine_block(&address).unwrap();
 
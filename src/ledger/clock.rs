@@ -0,0 +1,202 @@
+//! # Ledger Clock
+//!
+//! Ledger has its own clock, which can be set to any time with
+//! [`Ledger::set_mock_time`], like Bitcoin Core's `setmocktime`. This makes it
+//! possible to jump forward by days, without mining thousands of blocks.
+//!
+//! Without a mock time, the clock follows the system time. Blocks are still
+//! mined 10 minutes apart, so the clock never goes behind the tip of the
+//! active chain.
+//!
+//! Block timestamps must be greater than the median time past of their
+//! previous block and at most 2 hours ahead of the clock.
+
+use super::{errors::LedgerError, settings, Ledger};
+use bitcoin::{block::Header, BlockHash};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How far a block's time can be ahead of the clock, in seconds.
+pub const MAX_FUTURE_BLOCK_TIME: u32 = 2 * 60 * 60;
+/// Time between the blocks that are mined without a mock time, in seconds.
+const BLOCK_INTERVAL: u32 = 10 * 60;
+
+impl Ledger {
+    /// Sets the clock of the ledger to `time`, in UNIX format. Setting it to 0
+    /// makes the clock follow the system time again.
+    pub fn set_mock_time(&self, time: u32) -> Result<(), LedgerError> {
+        self.set_setting(settings::MOCK_TIME, time as i64)
+    }
+
+    /// Returns the mock time of the ledger, if it is set.
+    pub fn get_mock_time(&self) -> Result<Option<u32>, LedgerError> {
+        Ok(self
+            .get_setting(settings::MOCK_TIME)?
+            .filter(|time| *time != 0)
+            .map(|time| time as u32))
+    }
+
+    /// Returns current time of the ledger's clock, in UNIX format. This is the
+    /// mock time if it is set. Otherwise, it is the system time, or the time of
+    /// the tip or the configured first block time, if they are later.
+    pub fn get_time(&self) -> Result<u32, LedgerError> {
+        if let Some(time) = self.get_mock_time()? {
+            return Ok(time);
+        }
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as u32;
+        let tip_time = self.get_block_time(self.get_block_height()?)?;
+        let first_block_time = self.get_setting(settings::TIME)?.unwrap_or(0) as u32;
+
+        Ok(now.max(tip_time).max(first_block_time))
+    }
+
+    /// Returns the time of a new block on top of the block with
+    /// `prev_blockhash`.
+    ///
+    /// If a mock time is set, it is used. Otherwise, first block gets the
+    /// configured time or the system time and other blocks are 10 minutes
+    /// after their previous block. In both cases, time is bumped to be greater
+    /// than the median time past of the previous block.
+    pub fn get_next_block_time(&self, prev_blockhash: BlockHash) -> Result<u32, LedgerError> {
+        let min_time = self.get_median_time_past(prev_blockhash)?.saturating_add(1);
+
+        if let Some(time) = self.get_mock_time()? {
+            return Ok(time.max(min_time));
+        }
+
+        let (prev_height, prev_time) = self.get_block_height_and_time(prev_blockhash)?;
+        let time = match prev_height {
+            0 => match self.get_setting(settings::TIME)? {
+                Some(time) => time as u32,
+                None => self.get_time()?,
+            },
+            _ => prev_time.saturating_add(BLOCK_INTERVAL),
+        };
+
+        Ok(time.max(min_time))
+    }
+
+    /// Checks if the time of the block with `header` is greater than the median
+    /// time past of its previous block and it is not more than 2 hours ahead
+    /// of the clock.
+    pub fn check_block_time(&self, header: &Header) -> Result<(), LedgerError> {
+        let median_time_past = self.get_median_time_past(header.prev_blockhash)?;
+        if header.time <= median_time_past {
+            return Err(LedgerError::Block(format!(
+                "Block {} has time {}, which is not greater than the median time past {}",
+                header.block_hash(),
+                header.time,
+                median_time_past
+            )));
+        }
+
+        let max_time = self.get_time()?.saturating_add(MAX_FUTURE_BLOCK_TIME);
+        if header.time > max_time {
+            return Err(LedgerError::Block(format!(
+                "Block {} has time {}, which is more than 2 hours in the future (max {})",
+                header.block_hash(),
+                header.time,
+                max_time
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MAX_FUTURE_BLOCK_TIME;
+    use crate::ledger::Ledger;
+
+    #[test]
+    fn mock_time() {
+        let ledger = Ledger::new("memory://mock_time?time=1700000000").unwrap();
+        let address = Ledger::generate_credential_from_witness().address;
+
+        assert_eq!(ledger.get_mock_time().unwrap(), None);
+        assert!(ledger.get_time().unwrap() >= 1700000000);
+
+        ledger.mine_block(&address).unwrap();
+        ledger.mine_block(&address).unwrap();
+        assert_eq!(ledger.get_block_time(2).unwrap(), 1700000600);
+
+        // Jump forward by 30 days.
+        let time = 1700000600 + 30 * 24 * 60 * 60;
+        ledger.set_mock_time(time).unwrap();
+        assert_eq!(ledger.get_time().unwrap(), time);
+        ledger.mine_block(&address).unwrap();
+        assert_eq!(ledger.get_block_time(3).unwrap(), time);
+
+        // Blocks with the same mock time are bumped above the median time
+        // past.
+        for _ in 0..10 {
+            ledger.mine_block(&address).unwrap();
+        }
+        let tip = ledger.get_block_hash(13).unwrap();
+        assert!(ledger.get_block_time(13).unwrap() > time);
+        assert!(
+            ledger.get_block_time(13).unwrap()
+                > ledger
+                    .get_median_time_past(ledger.get_block_hash(12).unwrap())
+                    .unwrap()
+        );
+        assert_eq!(
+            ledger.get_next_block_time(tip).unwrap(),
+            ledger.get_median_time_past(tip).unwrap() + 1
+        );
+
+        // Clock follows the system time again, but doesn't go behind the tip.
+        ledger.set_mock_time(0).unwrap();
+        assert_eq!(ledger.get_mock_time().unwrap(), None);
+        assert!(ledger.get_time().unwrap() >= ledger.get_block_time(13).unwrap());
+    }
+
+    #[test]
+    fn check_block_time() {
+        let ledger = Ledger::new("memory://check_block_time?time=1700000000").unwrap();
+        let address = Ledger::generate_credential_from_witness().address;
+        for _ in 0..11 {
+            ledger.mine_block(&address).unwrap();
+        }
+        let tip = ledger.get_block_hash(11).unwrap();
+        let median_time_past = ledger.get_median_time_past(tip).unwrap();
+        ledger.set_mock_time(1700010000).unwrap();
+
+        let block = |time| ledger.build_block(tip, time, vec![]).unwrap().header;
+
+        assert!(ledger.check_block_time(&block(median_time_past)).is_err());
+        ledger
+            .check_block_time(&block(median_time_past + 1))
+            .unwrap();
+        ledger
+            .check_block_time(&block(1700010000 + MAX_FUTURE_BLOCK_TIME))
+            .unwrap();
+        assert!(ledger
+            .check_block_time(&block(1700010000 + MAX_FUTURE_BLOCK_TIME + 1))
+            .is_err());
+
+        // Mining fails if the clock is set too far behind the chain.
+        ledger.set_mock_time(1600000000).unwrap();
+        assert!(ledger.mine_block(&address).is_err());
+        assert_eq!(ledger.get_block_height().unwrap(), 11);
+
+        assert!(ledger
+            .mine_block_at(&address, median_time_past + 1)
+            .is_err());
+        ledger.set_mock_time(0).unwrap();
+        ledger
+            .mine_block_at(&address, median_time_past + 1)
+            .unwrap();
+        assert_eq!(ledger.get_block_time(12).unwrap(), median_time_past + 1);
+        assert!(ledger.mine_block_at(&address, median_time_past).is_err());
+
+        // Clock can be at the end of time.
+        ledger.set_mock_time(u32::MAX).unwrap();
+        ledger.mine_block(&address).unwrap();
+        assert_eq!(ledger.get_block_time(13).unwrap(), u32::MAX);
+    }
+}
@@ -2,6 +2,7 @@
 //!
 //! Errors that can be returned from ledger operations.
 
+use bitcoin::OutPoint;
 use thiserror::Error;
 
 /// Ledger error types.
@@ -13,6 +14,10 @@ pub enum LedgerError {
     InputFundsNotEnough(u64),
     #[error("UTXO error: {0}")]
     Utxo(String),
+    #[error("Input {0} is either spent or doesn't exist")]
+    InputMissingOrSpent(OutPoint),
+    #[error("Input {0} spends a coinbase output that is not mature yet")]
+    ImmatureCoinbase(OutPoint),
     #[error("SpendingRequirements error: {0}")]
     SpendingRequirements(String),
     #[error("Script error: {0}")]
@@ -23,6 +28,14 @@ pub enum LedgerError {
     BlockInMempool(u32),
     #[error("RPC error: {0}")]
     Rpc(String),
+    #[error("Database error: {0}")]
+    Database(String),
+    #[error("Snapshot error: {0}")]
+    Snapshot(String),
+    #[error("Database schema error: {0}")]
+    Schema(String),
+    #[error("Address error: {0}")]
+    Address(String),
 }
 
 impl From<LedgerError> for bitcoincore_rpc::Error {
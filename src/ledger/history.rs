@@ -0,0 +1,232 @@
+//! # Script History Index
+//!
+//! Every transaction in the ledger is indexed by the scripts it pays to and
+//! spends from. This makes it possible to find deposits to and withdrawals
+//! from an address, without scanning all the transactions.
+
+use super::{errors::LedgerError, utxo::Utxo, Ledger};
+use bitcoin::{Script, ScriptBuf, Transaction, Txid};
+use rusqlite::params;
+use std::str::FromStr;
+
+/// A transaction that pays to or spends from a script.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HistoryEntry {
+    pub txid: Txid,
+    /// Height of the block that includes the transaction. `None` if
+    /// transaction is in mempool.
+    pub height: Option<u32>,
+}
+
+impl Ledger {
+    /// Adds `transaction` to the history of every script it pays to or spends
+    /// from. Inputs that are not known by the ledger are skipped.
+    pub fn add_transaction_history(&self, transaction: &Transaction) -> Result<(), LedgerError> {
+        let txid = transaction.compute_txid();
+
+        let mut scripts: Vec<ScriptBuf> = transaction
+            .output
+            .iter()
+            .map(|output| output.script_pubkey.clone())
+            .collect();
+        if !transaction.is_coinbase() {
+            for input in &transaction.input {
+                let utxo = self
+                    .get_utxo(input.previous_output)
+                    .or_else(|_| self.get_utxo_from_transaction(input.previous_output));
+
+                if let Ok(utxo) = utxo {
+                    scripts.push(utxo.txout.script_pubkey);
+                }
+            }
+        }
+
+        let database = self.database.lock().unwrap();
+        for script in scripts {
+            if let Err(e) = database.execute(
+                "INSERT OR IGNORE INTO history (script_pubkey, txid) VALUES (?1, ?2)",
+                params![script.as_bytes(), txid.to_string()],
+            ) {
+                return Err(LedgerError::Transaction(format!(
+                    "Couldn't add transaction {} to script history: {}",
+                    txid, e
+                )));
+            };
+        }
+
+        Ok(())
+    }
+
+    /// Removes transaction with `txid` from every script's history.
+    pub fn remove_transaction_history(&self, txid: Txid) -> Result<(), LedgerError> {
+        if let Err(e) = self.database.lock().unwrap().execute(
+            "DELETE FROM history WHERE txid = ?1",
+            params![txid.to_string()],
+        ) {
+            return Err(LedgerError::Transaction(format!(
+                "Couldn't remove transaction {} from script history: {}",
+                txid, e
+            )));
+        };
+
+        Ok(())
+    }
+
+    /// Returns every transaction that pays to or spends from `script`, in
+    /// block order. Mempool transactions are at the end.
+    pub fn get_history(&self, script: &Script) -> Result<Vec<HistoryEntry>, LedgerError> {
+        let database = self.database.lock().unwrap();
+
+        let mut stmt = database
+            .prepare(
+                "SELECT history.txid, transactions.block_height, mempool.txid IS NULL
+                FROM history
+                JOIN transactions ON transactions.txid = history.txid
+                LEFT JOIN mempool ON mempool.txid = history.txid
+                WHERE history.script_pubkey = ?1
+                ORDER BY transactions.block_height, history.rowid",
+            )
+            .unwrap();
+        let history = stmt
+            .query_map(params![script.as_bytes()], |row| {
+                let txid: String = row.get(0)?;
+                let is_confirmed: bool = row.get(2)?;
+
+                Ok(HistoryEntry {
+                    txid: Txid::from_str(&txid).unwrap(),
+                    height: if is_confirmed {
+                        Some(row.get(1)?)
+                    } else {
+                        None
+                    },
+                })
+            })
+            .and_then(|rows| rows.collect::<Result<Vec<HistoryEntry>, rusqlite::Error>>());
+
+        match history {
+            Ok(history) => Ok(history),
+            Err(e) => Err(LedgerError::Transaction(format!(
+                "Couldn't read history of script {}: {}",
+                script, e
+            ))),
+        }
+    }
+
+    /// Returns unspent outputs that are locked to `script`, in block order.
+    /// Outputs of the mempool transactions are included.
+    pub fn get_utxos(&self, script: &Script) -> Result<Vec<Utxo>, LedgerError> {
+        let database = self.database.lock().unwrap();
+
+        let mut stmt = database
+            .prepare(
+                "SELECT txid, vout, value, script_pubkey, height, coinbase FROM utxos
+                WHERE script_pubkey = ?1
+                ORDER BY height, rowid",
+            )
+            .unwrap();
+        let utxos = stmt
+            .query_map(params![script.as_bytes()], Utxo::from_row)
+            .and_then(|rows| rows.collect::<Result<Vec<Utxo>, rusqlite::Error>>());
+
+        match utxos {
+            Ok(utxos) => Ok(utxos),
+            Err(e) => Err(LedgerError::Utxo(format!(
+                "Couldn't read UTXOs of script {}: {}",
+                script, e
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HistoryEntry;
+    use crate::ledger::Ledger;
+    use bitcoin::{Amount, OutPoint};
+
+    #[test]
+    fn history_and_utxos() {
+        let ledger = Ledger::new("history_and_utxos").unwrap();
+        let miner = Ledger::generate_credential_from_witness().address;
+        let receiver = Ledger::generate_credential_from_witness().address;
+
+        ledger.mine_block(&miner).unwrap();
+        let coinbase = ledger.get_block_with_height(1).unwrap().txdata[0].clone();
+        let coinbase_txid = coinbase.compute_txid();
+
+        assert!(ledger
+            .get_history(&receiver.script_pubkey())
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            ledger.get_history(&miner.script_pubkey()).unwrap(),
+            vec![HistoryEntry {
+                txid: coinbase_txid,
+                height: Some(1)
+            }]
+        );
+
+        // Send some funds from miner to receiver.
+        let txin = ledger.create_txin(coinbase_txid, 0);
+        let txout = ledger.create_txout(Amount::from_sat(0x45), receiver.script_pubkey());
+        let tx = ledger.create_transaction(vec![txin], vec![txout]);
+        let txid = ledger.add_transaction_unconditionally(tx).unwrap();
+
+        let deposit = HistoryEntry { txid, height: None };
+        assert_eq!(
+            ledger.get_history(&receiver.script_pubkey()).unwrap(),
+            vec![deposit.clone()]
+        );
+        // Spending from a script is also a part of it's history.
+        assert_eq!(
+            ledger.get_history(&miner.script_pubkey()).unwrap()[1],
+            deposit
+        );
+
+        let utxos = ledger.get_utxos(&receiver.script_pubkey()).unwrap();
+        assert_eq!(utxos.len(), 1);
+        assert_eq!(utxos[0].outpoint, OutPoint { txid, vout: 0 });
+        assert_eq!(utxos[0].txout.value, Amount::from_sat(0x45));
+        assert!(ledger
+            .get_utxos(&miner.script_pubkey())
+            .unwrap()
+            .iter()
+            .all(|utxo| utxo.outpoint.txid != coinbase_txid));
+
+        ledger.mine_block(&miner).unwrap();
+        assert_eq!(
+            ledger.get_history(&receiver.script_pubkey()).unwrap(),
+            vec![HistoryEntry {
+                txid,
+                height: Some(2)
+            }]
+        );
+    }
+
+    #[test]
+    fn history_after_reorg() {
+        let ledger = Ledger::new("history_after_reorg").unwrap();
+        let address = Ledger::generate_credential_from_witness().address;
+
+        let hash = ledger.mine_block(&address).unwrap();
+        ledger.mine_block(&address).unwrap();
+        assert_eq!(
+            ledger.get_history(&address.script_pubkey()).unwrap().len(),
+            2
+        );
+
+        // Coinbase of the invalidated block is removed from the ledger.
+        ledger
+            .invalidate_block(ledger.get_block_hash(2).unwrap())
+            .unwrap();
+        let history = ledger.get_history(&address.script_pubkey()).unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].height, Some(1));
+        assert_eq!(
+            ledger.get_utxos(&address.script_pubkey()).unwrap()[0]
+                .outpoint
+                .txid,
+            ledger.get_block_with_hash(hash).unwrap().txdata[0].compute_txid()
+        );
+    }
+}
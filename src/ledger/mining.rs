@@ -0,0 +1,447 @@
+//! # Block Assembly
+//!
+//! Mempool transactions are selected for a block like Bitcoin Core's
+//! `BlockAssembler` does: Every transaction is considered as a package with
+//! its unconfirmed ancestors and packages are added in descending ancestor
+//! feerate order. Packages that would exceed the block weight or sigop cost
+//! limit are skipped. Their transactions stay in the mempool for the next
+//! blocks.
+//!
+//! Transactions that are not final in the next block or that have unsatisfied
+//! relative lock times, and their descendants, are never selected.
+
+use super::{errors::LedgerError, Ledger};
+use bitcoin::{constants::MAX_BLOCK_SIGOPS_COST, Amount, Block, Transaction, Txid, Weight};
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+/// Weight reserved for the block header and the coinbase transaction.
+const COINBASE_RESERVED_WEIGHT: Weight = Weight::from_wu(4_000);
+/// Sigop cost reserved for the coinbase transaction.
+const COINBASE_RESERVED_SIGOPS_COST: usize = 400;
+
+/// A mempool transaction with the values that are needed to select it.
+struct MempoolEntry {
+    fee: Amount,
+    weight: Weight,
+    sigops_cost: usize,
+    /// Indexes of the mempool transactions that this transaction spends from.
+    parents: Vec<usize>,
+    /// Indexes of the mempool transactions that spend from this transaction.
+    children: Vec<usize>,
+}
+
+/// Total fee, weight and sigop cost of a transaction and its unselected
+/// ancestors.
+#[derive(Clone, Copy)]
+struct PackageScore {
+    fee: Amount,
+    weight: Weight,
+    sigops_cost: usize,
+}
+
+impl PackageScore {
+    /// Returns true if this package pays a higher feerate than `other`.
+    fn is_better_than(&self, other: &PackageScore) -> bool {
+        self.fee.to_sat() as u128 * other.weight.to_wu() as u128
+            > other.fee.to_sat() as u128 * self.weight.to_wu() as u128
+    }
+}
+
+impl Ledger {
+    /// Selects transactions for a block from `mempool`, which must be in
+    /// mempool order: Parents come before their children. Returned
+    /// transactions are in the order they should be in the block.
+    ///
+    /// Transactions that are not selected would make the block exceed
+    /// its weight or sigop cost limit or they are locked yet.
+    pub fn select_block_transactions(
+        &self,
+        mempool: &[Transaction],
+    ) -> Result<Vec<Transaction>, LedgerError> {
+        let tip_height = self.get_block_height()?;
+        let median_time_past = self.get_median_time_past(self.get_block_hash(tip_height)?)?;
+
+        let mut indexes: HashMap<Txid, usize> = HashMap::new();
+        let mut entries: Vec<MempoolEntry> = Vec::new();
+        let mut failed: HashSet<usize> = HashSet::new();
+
+        for (index, transaction) in mempool.iter().enumerate() {
+            let parents: Vec<usize> = transaction
+                .input
+                .iter()
+                .filter_map(|input| indexes.get(&input.previous_output.txid).copied())
+                .collect::<BTreeSet<usize>>()
+                .into_iter()
+                .collect();
+            for parent in &parents {
+                entries[*parent].children.push(index);
+            }
+
+            if !Ledger::is_transaction_final(transaction, tip_height + 1, median_time_past)
+                || self.check_sequence_locks(transaction).is_err()
+                || parents.iter().any(|parent| failed.contains(parent))
+            {
+                tracing::trace!("Transaction {} is locked", transaction.compute_txid());
+                failed.insert(index);
+            }
+
+            entries.push(MempoolEntry {
+                fee: self.calculate_transaction_fee(transaction),
+                weight: transaction.weight(),
+                sigops_cost: self.get_transaction_sigops_cost(transaction),
+                parents,
+                children: Vec::new(),
+            });
+            indexes.insert(transaction.compute_txid(), index);
+        }
+
+        let mut selected: Vec<usize> = Vec::new();
+        let mut is_selected: HashSet<usize> = HashSet::new();
+        let mut scores: Vec<Option<PackageScore>> = vec![None; entries.len()];
+        let mut block_weight = COINBASE_RESERVED_WEIGHT;
+        let mut block_sigops_cost = COINBASE_RESERVED_SIGOPS_COST;
+
+        loop {
+            let mut best: Option<(usize, PackageScore)> = None;
+
+            for (index, cached_score) in scores.iter_mut().enumerate() {
+                if is_selected.contains(&index) || failed.contains(&index) {
+                    continue;
+                }
+
+                let score = *cached_score.get_or_insert_with(|| {
+                    Ledger::get_package(&entries, index, &is_selected)
+                        .iter()
+                        .fold(
+                            PackageScore {
+                                fee: Amount::ZERO,
+                                weight: Weight::ZERO,
+                                sigops_cost: 0,
+                            },
+                            |score, i| PackageScore {
+                                fee: score.fee + entries[*i].fee,
+                                weight: score.weight + entries[*i].weight,
+                                sigops_cost: score.sigops_cost + entries[*i].sigops_cost,
+                            },
+                        )
+                });
+
+                let is_best = match best {
+                    Some((_, best)) => score.is_better_than(&best),
+                    None => true,
+                };
+                if is_best {
+                    best = Some((index, score));
+                }
+            }
+
+            let Some((index, score)) = best else {
+                break;
+            };
+
+            if block_weight + score.weight > Weight::MAX_BLOCK
+                || block_sigops_cost + score.sigops_cost > MAX_BLOCK_SIGOPS_COST as usize
+            {
+                tracing::trace!("Package of transaction {} doesn't fit in block", index);
+                failed.insert(index);
+                continue;
+            }
+
+            block_weight += score.weight;
+            block_sigops_cost += score.sigops_cost;
+
+            for i in Ledger::get_package(&entries, index, &is_selected) {
+                is_selected.insert(i);
+                selected.push(i);
+
+                // Scores of the descendants include this transaction.
+                let mut descendants = entries[i].children.clone();
+                while let Some(descendant) = descendants.pop() {
+                    if scores[descendant].take().is_some() {
+                        descendants.extend(entries[descendant].children.iter());
+                    }
+                }
+            }
+        }
+        tracing::debug!(
+            "Selected {} of {} mempool transactions, block weight: {}, sigop cost: {}",
+            selected.len(),
+            mempool.len(),
+            block_weight,
+            block_sigops_cost
+        );
+
+        Ok(selected
+            .into_iter()
+            .map(|index| mempool[index].clone())
+            .collect())
+    }
+
+    /// Returns the transaction at `index` and its ancestors that are not
+    /// selected, in mempool order.
+    fn get_package(
+        entries: &[MempoolEntry],
+        index: usize,
+        is_selected: &HashSet<usize>,
+    ) -> BTreeSet<usize> {
+        let mut package = BTreeSet::new();
+        let mut stack = vec![index];
+
+        while let Some(i) = stack.pop() {
+            if is_selected.contains(&i) || !package.insert(i) {
+                continue;
+            }
+
+            stack.extend(entries[i].parents.iter());
+        }
+
+        package
+    }
+
+    /// Returns sigop cost of a transaction. Sigops in the spent outputs are
+    /// only counted if they are known by the ledger.
+    pub fn get_transaction_sigops_cost(&self, transaction: &Transaction) -> usize {
+        transaction.total_sigop_cost(|outpoint| {
+            self.get_utxo_from_transaction(*outpoint)
+                .ok()
+                .map(|utxo| utxo.txout)
+        })
+    }
+
+    /// Checks if `block` is within the block weight and sigop cost limits.
+    pub fn check_block_limits(&self, block: &Block) -> Result<(), LedgerError> {
+        let weight = block.weight();
+        if weight > Weight::MAX_BLOCK {
+            return Err(LedgerError::Block(format!(
+                "Block {} has weight {}, which is more than the limit {}",
+                block.block_hash(),
+                weight,
+                Weight::MAX_BLOCK
+            )));
+        }
+
+        let sigops_cost: usize = block
+            .txdata
+            .iter()
+            .map(|tx| self.get_transaction_sigops_cost(tx))
+            .sum();
+        if sigops_cost > MAX_BLOCK_SIGOPS_COST as usize {
+            return Err(LedgerError::Block(format!(
+                "Block {} has sigop cost {}, which is more than the limit {}",
+                block.block_hash(),
+                sigops_cost,
+                MAX_BLOCK_SIGOPS_COST
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ledger::Ledger, utils::INITIAL_BLOCK_SUBSIDY};
+    use bitcoin::{absolute, Address, Amount, OutPoint, ScriptBuf, Sequence, Transaction};
+
+    /// Mines a block and splits its coinbase to `count` outputs of 1 BTC.
+    fn fund(ledger: &Ledger, address: &Address, count: u32) -> Vec<OutPoint> {
+        ledger.mine_block(address).unwrap();
+        let coinbase = ledger.get_block_with_height(1).unwrap().txdata[0].clone();
+
+        let tx = ledger.create_transaction(
+            vec![ledger.create_txin(coinbase.compute_txid(), 0)],
+            (0..count)
+                .map(|_| ledger.create_txout(Amount::ONE_BTC, address.script_pubkey()))
+                .collect(),
+        );
+        let txid = ledger.add_transaction_unconditionally(tx).unwrap();
+        ledger.mine_block(address).unwrap();
+
+        (0..count).map(|vout| OutPoint { txid, vout }).collect()
+    }
+
+    #[test]
+    fn select_by_ancestor_feerate() {
+        let ledger = Ledger::new("memory://select_by_ancestor_feerate").unwrap();
+        let address = Ledger::generate_credential_from_witness().address;
+        let outpoints = fund(&ledger, &address, 3);
+
+        let low = ledger.create_transaction(
+            vec![ledger.create_txin(outpoints[0].txid, outpoints[0].vout)],
+            vec![ledger.create_txout(
+                Amount::ONE_BTC - Amount::from_sat(100),
+                address.script_pubkey(),
+            )],
+        );
+        let high = ledger.create_transaction(
+            vec![ledger.create_txin(outpoints[1].txid, outpoints[1].vout)],
+            vec![ledger.create_txout(
+                Amount::ONE_BTC - Amount::from_sat(10_000),
+                address.script_pubkey(),
+            )],
+        );
+        let parent = ledger.create_transaction(
+            vec![ledger.create_txin(outpoints[2].txid, outpoints[2].vout)],
+            vec![ledger.create_txout(
+                Amount::ONE_BTC - Amount::from_sat(1),
+                address.script_pubkey(),
+            )],
+        );
+        for tx in [&low, &high, &parent] {
+            ledger.add_transaction_unconditionally(tx.clone()).unwrap();
+        }
+        // Child pays for its parent.
+        let child = ledger.create_transaction(
+            vec![ledger.create_txin(parent.compute_txid(), 0)],
+            vec![ledger.create_txout(
+                Amount::ONE_BTC - Amount::from_sat(1 + 1_000),
+                address.script_pubkey(),
+            )],
+        );
+        ledger
+            .add_transaction_unconditionally(child.clone())
+            .unwrap();
+
+        let selected = ledger
+            .select_block_transactions(&ledger.get_mempool_transactions())
+            .unwrap();
+        assert_eq!(selected, vec![high, parent, child, low]);
+    }
+
+    #[test]
+    fn skip_non_final_transactions() {
+        let ledger = Ledger::new("memory://skip_non_final_transactions").unwrap();
+        let address = Ledger::generate_credential_from_witness().address;
+        let outpoints = fund(&ledger, &address, 2);
+
+        // Lock time is block 5, so it can be mined in block 6 and later. Next
+        // block is 3.
+        let mut locked = ledger.create_transaction(
+            vec![ledger.create_txin(outpoints[0].txid, outpoints[0].vout)],
+            vec![ledger.create_txout(
+                Amount::ONE_BTC - Amount::from_sat(1_000),
+                address.script_pubkey(),
+            )],
+        );
+        locked.lock_time = absolute::LockTime::from_height(5).unwrap();
+        locked.input[0].sequence = Sequence::ENABLE_LOCKTIME_NO_RBF;
+        ledger
+            .add_transaction_unconditionally(locked.clone())
+            .unwrap();
+        let child = ledger.create_transaction(
+            vec![ledger.create_txin(locked.compute_txid(), 0)],
+            vec![ledger.create_txout(
+                Amount::ONE_BTC - Amount::from_sat(1_000 + 1_000),
+                address.script_pubkey(),
+            )],
+        );
+        let other = ledger.create_transaction(
+            vec![ledger.create_txin(outpoints[1].txid, outpoints[1].vout)],
+            vec![ledger.create_txout(
+                Amount::ONE_BTC - Amount::from_sat(100),
+                address.script_pubkey(),
+            )],
+        );
+        for tx in [&child, &other] {
+            ledger.add_transaction_unconditionally(tx.clone()).unwrap();
+        }
+
+        let selected = ledger
+            .select_block_transactions(&ledger.get_mempool_transactions())
+            .unwrap();
+        assert_eq!(selected, vec![other.clone()]);
+
+        for _ in 0..3 {
+            ledger.mine_block(&address).unwrap();
+        }
+        let selected = ledger
+            .select_block_transactions(&ledger.get_mempool_transactions())
+            .unwrap();
+        assert_eq!(selected, vec![locked, child]);
+    }
+
+    #[test]
+    fn weight_limit() {
+        let ledger = Ledger::new("memory://weight_limit").unwrap();
+        let address = Ledger::generate_credential_from_witness().address;
+        let outpoints = fund(&ledger, &address, 3);
+
+        // Each transaction weighs more than 1.6M weight units, so only 2 of
+        // them fit in a block.
+        let script = ScriptBuf::from_bytes(vec![0x6a; 400_000]);
+        let txs: Vec<Transaction> = outpoints
+            .iter()
+            .zip([300, 200, 100])
+            .map(|(outpoint, fee)| {
+                ledger.create_transaction(
+                    vec![ledger.create_txin(outpoint.txid, outpoint.vout)],
+                    vec![ledger
+                        .create_txout(Amount::ONE_BTC - Amount::from_sat(fee), script.clone())],
+                )
+            })
+            .collect();
+        for tx in &txs {
+            ledger.add_transaction_unconditionally(tx.clone()).unwrap();
+        }
+
+        ledger.mine_block(&address).unwrap();
+        let block = ledger.get_block_with_height(3).unwrap();
+        assert_eq!(block.txdata[1..], txs[..2]);
+        assert_eq!(
+            block.txdata[0].output[0].value,
+            Amount::from_sat(INITIAL_BLOCK_SUBSIDY + 500)
+        );
+        assert_eq!(ledger.get_mempool_transactions(), txs[2..]);
+
+        // Transaction that is left in the mempool is mined in the next block.
+        ledger.mine_block(&address).unwrap();
+        assert_eq!(ledger.get_block_with_height(4).unwrap().txdata[1], txs[2]);
+        assert_eq!(
+            ledger
+                .get_transaction_block_height(&txs[2].compute_txid())
+                .unwrap(),
+            4
+        );
+        assert!(ledger.get_mempool_transactions().is_empty());
+    }
+
+    #[test]
+    fn sigops_limit() {
+        let ledger = Ledger::new("memory://sigops_limit").unwrap();
+        let address = Ledger::generate_credential_from_witness().address;
+        let outpoints = fund(&ledger, &address, 2);
+
+        // Every OP_CHECKMULTISIG costs 80, so each transaction costs 40080
+        // and only one of them fits in a block.
+        let script = ScriptBuf::from_bytes(vec![0xae; 501]);
+        let txs: Vec<Transaction> = outpoints
+            .iter()
+            .zip([200, 100])
+            .map(|(outpoint, fee)| {
+                ledger.create_transaction(
+                    vec![ledger.create_txin(outpoint.txid, outpoint.vout)],
+                    vec![ledger
+                        .create_txout(Amount::ONE_BTC - Amount::from_sat(fee), script.clone())],
+                )
+            })
+            .collect();
+        assert_eq!(ledger.get_transaction_sigops_cost(&txs[0]), 40_080);
+        for tx in &txs {
+            ledger.add_transaction_unconditionally(tx.clone()).unwrap();
+        }
+
+        // A block with both of them is rejected.
+        let coinbase = ledger
+            .create_coinbase_transaction(&address, vec![], 3, Amount::ZERO, &Default::default())
+            .unwrap();
+        let block = ledger
+            .create_block([vec![coinbase], txs.clone()].concat())
+            .unwrap();
+        assert!(ledger.check_block_limits(&block).is_err());
+
+        ledger.mine_block(&address).unwrap();
+        let block = ledger.get_block_with_height(3).unwrap();
+        assert_eq!(block.txdata[1..], txs[..1]);
+        assert_eq!(ledger.get_mempool_transactions(), txs[1..]);
+    }
+}
@@ -6,26 +6,132 @@
 //! immutable nature.
 
 use crate::utils;
-use rusqlite::Connection;
+use errors::LedgerError;
+use rusqlite::{backup::Backup, Connection};
 use std::{
+    collections::HashMap,
     env,
     process::Command,
-    sync::{Arc, Mutex},
+    sync::{Arc, Condvar, LockResult, Mutex, MutexGuard, OnceLock, Weak},
+    thread::{self, ThreadId},
+    time::Duration,
 };
 
 pub mod address;
 mod block;
+mod clock;
 pub(crate) mod errors;
+mod history;
+mod mining;
+mod reorg;
+mod schema;
 mod script;
+mod settings;
+mod snapshot;
 mod spending_requirements;
 mod transactions;
 mod utxo;
 
+pub use history::HistoryEntry;
+pub use transactions::CoinbaseOptions;
+
+/// Ledgers with this prefix are kept in memory, instead of a file.
+const MEMORY_LEDGER_PREFIX: &str = "memory://";
+
+/// Ledgers with this prefix are kept at the given file path and never cleaned.
+const FILE_LEDGER_PREFIX: &str = "file://";
+
+/// In-memory ledgers that are open in this process. Entries are weak, so that
+/// a ledger is freed when it's last handle is dropped.
+static MEMORY_LEDGERS: OnceLock<Mutex<HashMap<String, Weak<Database>>>> = OnceLock::new();
+
 /// Mock Bitcoin ledger.
 #[derive(Clone, Debug)]
 pub struct Ledger {
     /// Database connection.
-    database: Arc<Mutex<Connection>>,
+    database: Arc<Database>,
+}
+
+/// Database connection that is shared between the clones of a ledger.
+///
+/// While a thread runs an operation with [`Ledger::atomically`], other threads
+/// wait for it before accessing the database. Otherwise, their changes would
+/// end up in the operation's savepoint and could be rolled back with it.
+#[derive(Debug)]
+struct Database {
+    connection: Mutex<Connection>,
+    /// Thread that runs an atomic operation and how deeply they are nested.
+    owner: Mutex<Option<(ThreadId, usize)>>,
+    /// Notified when the atomic operations of the owner thread finish.
+    released: Condvar,
+}
+
+impl Database {
+    fn new(connection: Connection) -> Self {
+        Self {
+            connection: Mutex::new(connection),
+            owner: Mutex::new(None),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Locks the connection for a single statement. Waits for the atomic
+    /// operation of another thread, if there is one.
+    fn lock(&self) -> LockResult<MutexGuard<'_, Connection>> {
+        let current = thread::current().id();
+        let owner = self.owner.lock().unwrap();
+        let _owner = self
+            .released
+            .wait_while(
+                owner,
+                |owner| matches!(owner, Some((id, _)) if *id != current),
+            )
+            .unwrap();
+
+        self.connection.lock()
+    }
+
+    /// Makes the current thread the owner of the database, until the returned
+    /// guard is dropped. Can be nested.
+    fn own(&self) -> DatabaseOwnership<'_> {
+        let current = thread::current().id();
+        let owner = self.owner.lock().unwrap();
+        let mut owner = self
+            .released
+            .wait_while(
+                owner,
+                |owner| matches!(owner, Some((id, _)) if *id != current),
+            )
+            .unwrap();
+
+        *owner = match *owner {
+            Some((id, depth)) => Some((id, depth + 1)),
+            None => Some((current, 1)),
+        };
+
+        DatabaseOwnership { database: self }
+    }
+}
+
+/// Ownership of a database by the current thread. Other threads can access the
+/// database after it is dropped.
+struct DatabaseOwnership<'a> {
+    database: &'a Database,
+}
+
+impl Drop for DatabaseOwnership<'_> {
+    fn drop(&mut self) {
+        let mut owner = self.database.owner.lock().unwrap();
+
+        *owner = match *owner {
+            Some((id, depth)) if depth > 1 => Some((id, depth - 1)),
+            _ => None,
+        };
+
+        if owner.is_none() {
+            self.database.released.notify_all();
+        }
+    }
 }
 
 impl Ledger {
@@ -35,15 +141,59 @@ impl Ledger {
     /// `path`. This can be used to identify different databases created by
     /// different tests.
     ///
+    /// If `path` starts with `memory://`, database is kept in memory instead.
+    /// Ledgers with the same name share the same database in a process and
+    /// database is freed when the last ledger using it is dropped.
+    ///
+    /// If `path` starts with `file://`, database is kept at the rest of the
+    /// `path`, e.g. `file:///path/to/chain.db`. This database persists between
+    /// runs: Tables are created if they don't exist but never dropped.
+    ///
+    /// Existing databases are migrated to the latest schema version.
+    ///
+    /// `path` can have settings as parameters, like `name?seed=42&time=0`.
+    /// Please check `settings` module for details. Chain starts with the
+    /// genesis block of the ledger's network.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if database's schema is unsupported and can't be
+    /// migrated or settings in `path` are invalid.
+    ///
     /// # Panics
     ///
     /// Panics if SQLite connection can't be established and initial query can't
     /// be run.
     #[tracing::instrument]
-    pub fn new(path: &str) -> Self {
-        let path = Ledger::get_database_path(path);
+    pub fn new(path: &str) -> Result<Self, LedgerError> {
         let _ = utils::initialize_logger();
 
+        let (path, settings) = Ledger::parse_path_settings(path)?;
+
+        let ledger = Ledger::open(path)?;
+        ledger.atomically(|| {
+            for (name, value) in settings {
+                ledger.set_setting(name, value)?;
+            }
+
+            ledger.check_genesis_block()
+        })?;
+
+        Ok(ledger)
+    }
+
+    /// Opens the ledger at `path`, without any settings. Please check
+    /// [`Ledger::new`] for details.
+    fn open(path: &str) -> Result<Self, LedgerError> {
+        if let Some(name) = path.strip_prefix(MEMORY_LEDGER_PREFIX) {
+            return Ledger::open_in_memory(name);
+        }
+        if let Some(path) = path.strip_prefix(FILE_LEDGER_PREFIX) {
+            return Ledger::open_persistent(path);
+        }
+
+        let path = Ledger::get_database_path(path);
+
         // Check if database has another connections.
         let is_open = {
             let ret = Command::new("lsof")
@@ -60,14 +210,14 @@ impl Ledger {
         if !is_open {
             tracing::trace!("Creating new database at path {path}");
             Ledger::drop_tables(&database).unwrap();
-            Ledger::create_tables(&database).unwrap();
         }
+        Ledger::migrate(&database)?;
 
         tracing::trace!("Database connection to {path} is established");
 
-        Self {
-            database: Arc::new(Mutex::new(database)),
-        }
+        Ok(Self {
+            database: Arc::new(Database::new(database)),
+        })
     }
 
     /// Connects the ledger, previously created by the `new` call. This function
@@ -87,16 +237,114 @@ impl Ledger {
         since = "0.0.11",
         note = "`new()` also checks for open ledgers, not needed"
     )]
-    pub fn new_without_cleanup(path: &str) -> Self {
+    pub fn new_without_cleanup(path: &str) -> Result<Self, LedgerError> {
+        let (path, _) = Ledger::parse_path_settings(path)?;
+
+        if let Some(name) = path.strip_prefix(MEMORY_LEDGER_PREFIX) {
+            return Ledger::open_in_memory(name);
+        }
+        if let Some(path) = path.strip_prefix(FILE_LEDGER_PREFIX) {
+            return Ledger::open_persistent(path);
+        }
+
         let path = Ledger::get_database_path(path);
 
         let database = Connection::open(path.clone()).unwrap();
 
         tracing::trace!("Connecting to the existing database {path} without resetting");
 
-        Self {
-            database: Arc::new(Mutex::new(database)),
+        Ok(Self {
+            database: Arc::new(Database::new(database)),
+        })
+    }
+
+    /// Connects to the in-memory ledger named `name`, if it is open in this
+    /// process. Otherwise, creates a new one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if SQLite connection can't be established and initial query can't
+    /// be run.
+    fn open_in_memory(name: &str) -> Result<Self, LedgerError> {
+        let mut ledgers = MEMORY_LEDGERS
+            .get_or_init(|| Mutex::new(HashMap::new()))
+            .lock()
+            .unwrap();
+
+        if let Some(database) = ledgers.get(name).and_then(Weak::upgrade) {
+            tracing::trace!("Connecting to the existing in-memory database {name}");
+
+            return Ok(Self { database });
+        }
+
+        tracing::trace!("Creating new in-memory database {name}");
+        let database = Connection::open_in_memory().unwrap();
+        Ledger::migrate(&database)?;
+
+        let database = Arc::new(Database::new(database));
+        ledgers.retain(|_, ledger| ledger.strong_count() > 0);
+        ledgers.insert(name.to_owned(), Arc::downgrade(&database));
+
+        Ok(Self { database })
+    }
+
+    /// Connects to the persistent ledger at `path`. Tables are created if
+    /// database is new and migrated if it is old.
+    ///
+    /// # Panics
+    ///
+    /// Panics if SQLite connection can't be established and initial query can't
+    /// be run.
+    fn open_persistent(path: &str) -> Result<Self, LedgerError> {
+        let database = Connection::open(path).unwrap();
+        Ledger::migrate(&database)?;
+
+        tracing::trace!("Database connection to {path} is established");
+
+        Ok(Self {
+            database: Arc::new(Database::new(database)),
+        })
+    }
+
+    /// Creates a new ledger at `path`, which starts as an exact copy of this
+    /// one. After that, both ledgers evolve independently. `path` is handled
+    /// the same way as in [`Ledger::new`].
+    ///
+    /// Any data in the new ledger's database is overwritten.
+    #[tracing::instrument(skip(self))]
+    pub fn fork(&self, path: &str) -> Result<Ledger, LedgerError> {
+        let ledger = Ledger::new(path)?;
+
+        if Arc::ptr_eq(&self.database, &ledger.database) {
+            return Err(LedgerError::Database(format!(
+                "Can't fork ledger to itself: {path}"
+            )));
         }
+
+        {
+            let source = self.database.lock().unwrap();
+            let mut destination = ledger.database.lock().unwrap();
+
+            // In-memory databases have an empty path.
+            let is_same_file =
+                matches!(source.path(), Some(p) if !p.is_empty() && destination.path() == Some(p));
+            if is_same_file {
+                return Err(LedgerError::Database(format!(
+                    "Can't fork ledger to itself: {path}"
+                )));
+            }
+
+            if let Err(e) = Backup::new(&source, &mut destination)
+                .and_then(|backup| backup.run_to_completion(1024, Duration::ZERO, None))
+            {
+                return Err(LedgerError::Database(format!(
+                    "Couldn't copy ledger to {path}: {e}"
+                )));
+            }
+        }
+        tracing::trace!("Ledger is copied to {path}");
+
+        Ok(ledger)
     }
 
     fn get_database_path(path: &str) -> String {
@@ -104,58 +352,55 @@ impl Ledger {
     }
 
     fn drop_tables(database: &Connection) -> Result<(), rusqlite::Error> {
+        for table in Ledger::get_snapshot_tables(database)? {
+            database.execute_batch(&format!("DROP TABLE {table}"))?;
+        }
+
         database.execute_batch(
             "
             DROP TABLE IF EXISTS blocks;
             DROP TABLE IF EXISTS mempool;
             DROP TABLE IF EXISTS transactions;
             DROP TABLE IF EXISTS utxos;
+            DROP TABLE IF EXISTS undo;
+            DROP TABLE IF EXISTS stale_blocks;
+            DROP TABLE IF EXISTS snapshots;
+            DROP TABLE IF EXISTS history;
+            DROP TABLE IF EXISTS settings;
+            DROP TABLE IF EXISTS schema_version;
             ",
         )
     }
 
-    /// This is where all the ledger data is kept. Note that it is not aimed to
-    /// hold all kind of information about the blockchain. Just holds enough
-    /// data to provide a persistent storage for the limited features that the
-    /// library provides.
-    fn create_tables(database: &Connection) -> Result<(), rusqlite::Error> {
-        database.execute_batch(
-            "
-            CREATE TABLE blocks
-            (
-                height    INTEGER  NOT NULL,
-                time      INTEGER  NOT NULL,
-                hash      TEXT     NOT NULL,
-                coinbase  TEXT     NOT NULL,
-                body      BLOB     NOT NULL
-
-                CONSTRAINT height PRIMARY KEY
-            );
-            INSERT INTO blocks (height, time, hash, coinbase, body) VALUES (0, 500000000, '00000000000000000000', 0, 0);
-
-            CREATE TABLE mempool
-            (
-                txid  TEXT  NOT NULL
-
-                CONSTRAINT txid PRIMARY KEY
-            );
-
-            CREATE TABLE transactions
-            (
-                txid          TEXT     NOT NULL,
-                block_height  INTEGER  NOT NULL,
-                body          BLOB     NOT NULL
-
-                CONSTRAINT txid PRIMARY KEY
-            );
-
-            CREATE TABLE utxos
-            (
-                txid          TEXT     NOT NULL,
-                vout          INTEGER  NOT NULL
-            );
-            ",
-        )
+    /// Runs `operation` atomically: If it returns an error, every database
+    /// change made while running it is reverted. Other threads can't access
+    /// the database until it finishes.
+    fn atomically<T>(
+        &self,
+        operation: impl FnOnce() -> Result<T, LedgerError>,
+    ) -> Result<T, LedgerError> {
+        let _ownership = self.database.own();
+
+        let savepoint = |sql: &str| {
+            self.database
+                .lock()
+                .unwrap()
+                .execute_batch(sql)
+                .map_err(|e| LedgerError::Database(format!("Couldn't execute {sql}: {e}")))
+        };
+
+        savepoint("SAVEPOINT ledger")?;
+
+        match operation() {
+            Ok(ret) => {
+                savepoint("RELEASE ledger")?;
+                Ok(ret)
+            }
+            Err(e) => {
+                savepoint("ROLLBACK TO ledger; RELEASE ledger")?;
+                Err(e)
+            }
+        }
     }
 }
 
@@ -165,6 +410,126 @@ mod tests {
 
     #[test]
     fn new() {
-        let _should_not_panic = Ledger::new("ledger_new");
+        let _should_not_panic = Ledger::new("ledger_new").unwrap();
+    }
+
+    #[test]
+    fn new_in_memory() {
+        let ledger = Ledger::new("memory://ledger_new_in_memory").unwrap();
+        let tx = ledger.create_transaction(vec![], vec![]);
+        ledger.add_transaction_unconditionally(tx).unwrap();
+
+        assert!(
+            !std::path::Path::new(&Ledger::get_database_path("memory://ledger_new_in_memory"))
+                .exists()
+        );
+
+        // Ledgers with the same name share the database.
+        let other = Ledger::new("memory://ledger_new_in_memory").unwrap();
+        assert_eq!(other.get_mempool_transactions().len(), 1);
+        assert!(Ledger::new("memory://another_ledger")
+            .unwrap()
+            .get_mempool_transactions()
+            .is_empty());
+
+        // Database is freed after the last ledger is dropped.
+        drop(ledger);
+        drop(other);
+        let ledger = Ledger::new("memory://ledger_new_in_memory").unwrap();
+        assert!(ledger.get_mempool_transactions().is_empty());
+    }
+
+    #[test]
+    fn new_persistent() {
+        let path = env::temp_dir().join("bitcoin_mock_rpc_ledger_new_persistent.db");
+        let _ = std::fs::remove_file(&path);
+        let url = format!("file://{}", path.to_str().unwrap());
+
+        let ledger = Ledger::new(&url).unwrap();
+        let address = Ledger::generate_credential_from_witness().address;
+        ledger.mine_block(&address).unwrap();
+        let tx = ledger.create_transaction(vec![], vec![]);
+        ledger.add_transaction_unconditionally(tx).unwrap();
+        drop(ledger);
+
+        // Reopening shouldn't clean anything.
+        let ledger = Ledger::new(&url).unwrap();
+        assert_eq!(ledger.get_block_height().unwrap(), 1);
+        assert_eq!(ledger.get_mempool_transactions().len(), 1);
+
+        ledger.mine_block(&address).unwrap();
+        assert_eq!(Ledger::new(&url).unwrap().get_block_height().unwrap(), 2);
+    }
+
+    #[test]
+    fn new_persistent_with_newer_schema() {
+        let path = env::temp_dir().join("bitcoin_mock_rpc_ledger_new_persistent_newer.db");
+        let _ = std::fs::remove_file(&path);
+        let url = format!("file://{}", path.to_str().unwrap());
+
+        let ledger = Ledger::new(&url).unwrap();
+        ledger
+            .database
+            .lock()
+            .unwrap()
+            .execute_batch("UPDATE schema_version SET version = version + 1")
+            .unwrap();
+        drop(ledger);
+
+        assert!(matches!(Ledger::new(&url), Err(LedgerError::Schema(_))));
+    }
+
+    #[test]
+    fn atomically_blocks_other_threads() {
+        let ledger = Ledger::new("memory://ledger_atomically_blocks_other_threads").unwrap();
+        let other = ledger.clone();
+
+        let (started, wait) = std::sync::mpsc::channel();
+        let handle = thread::spawn(move || {
+            wait.recv().unwrap();
+            let tx = other.create_transaction(vec![], vec![]);
+            other.add_transaction_unconditionally(tx).unwrap();
+        });
+
+        // Other thread's transaction is added after the rollback, so it is not
+        // reverted.
+        let ret: Result<(), LedgerError> = ledger.atomically(|| {
+            started.send(()).unwrap();
+            thread::sleep(Duration::from_millis(100));
+
+            Err(LedgerError::Database("Rolled back".to_owned()))
+        });
+        assert!(ret.is_err());
+
+        handle.join().unwrap();
+        assert_eq!(ledger.get_mempool_transactions().len(), 1);
+    }
+
+    #[test]
+    fn fork() {
+        let ledger = Ledger::new("ledger_fork").unwrap();
+        let address = Ledger::generate_credential_from_witness().address;
+        ledger.mine_block(&address).unwrap();
+        let tx = ledger.create_transaction(vec![], vec![]);
+        ledger.add_transaction_unconditionally(tx).unwrap();
+
+        for path in ["ledger_fork_copy", "memory://ledger_fork_copy"] {
+            let fork = ledger.fork(path).unwrap();
+            assert_eq!(fork.get_block_height().unwrap(), 1);
+            assert_eq!(fork.get_mempool_transactions().len(), 1);
+
+            // Ledgers evolve independently.
+            fork.mine_block(&address).unwrap();
+            assert_eq!(fork.get_block_height().unwrap(), 2);
+            assert!(fork.get_mempool_transactions().is_empty());
+            assert_eq!(ledger.get_block_height().unwrap(), 1);
+            assert_eq!(ledger.get_mempool_transactions().len(), 1);
+        }
+
+        assert!(ledger.fork("ledger_fork").is_err());
+        assert!(Ledger::new("memory://ledger_fork")
+            .unwrap()
+            .fork("memory://ledger_fork")
+            .is_err());
     }
 }
@@ -0,0 +1,988 @@
+//! # Chain Reorganization Related Ledger Operations
+//!
+//! Only the active chain is kept in the `blocks` table. Blocks that are
+//! disconnected from it, either by getting invalidated or by a better chain
+//! getting activated, are moved to the `stale_blocks` table.
+//!
+//! Ledger follows the branch with the most work. Blocks can be mined on top of
+//! any block, which makes it possible to grow competing branches.
+//!
+//! Every block in the active chain has undo data, which is the list of UTXOs
+//! spent by that block. It is used to roll back the UTXO set when the block is
+//! disconnected.
+
+use super::{errors::LedgerError, transactions::CoinbaseOptions, utxo::Utxo, Ledger};
+use bitcoin::{
+    consensus::{Decodable, Encodable},
+    Address, Amount, Block, BlockHash, OutPoint, Transaction, Txid, Work,
+};
+use bitcoincore_rpc::json::{self, GetChainTipsResultStatus, GetChainTipsResultTip};
+use rusqlite::params;
+use std::{cmp::Reverse, collections::HashSet};
+
+/// A block that is not in the active chain.
+#[derive(Clone, Debug)]
+struct StaleBlock {
+    block: Block,
+    height: u32,
+    time: u32,
+    /// Block was connected to the active chain before.
+    validated: bool,
+    invalid: bool,
+}
+
+impl Ledger {
+    /// Mines a block on top of the block with `prev_blockhash`. If it is the
+    /// tip of the active chain, this is the same as [`Ledger::mine_block`].
+    /// Otherwise, the new block will only have a coinbase transaction.
+    ///
+    /// If the new block makes it's branch the one with the most work, a
+    /// reorganization happens. If that fails, the new block is not kept.
+    #[tracing::instrument(skip(self))]
+    pub fn mine_block_on(
+        &self,
+        prev_blockhash: BlockHash,
+        address: &Address,
+    ) -> Result<BlockHash, LedgerError> {
+        if prev_blockhash == self.get_block_hash(self.get_block_height()?)? {
+            return self.mine_block(address);
+        }
+
+        let (prev_height, _) = self.get_block_height_and_time(prev_blockhash)?;
+        let height = prev_height + 1;
+        let time = self.get_next_block_time(prev_blockhash)?;
+
+        let coinbase_transaction = self.create_coinbase_transaction(
+            address,
+            vec![],
+            height,
+            Amount::ZERO,
+            &CoinbaseOptions::default(),
+        )?;
+        let block = self.build_block(prev_blockhash, time, vec![coinbase_transaction])?;
+        self.check_block_time(&block.header)?;
+        let block_hash = block.block_hash();
+        tracing::debug!("Mined block {block_hash} at height {height} in a stale branch");
+
+        self.atomically(|| {
+            self.add_stale_block(&block, height, time, false)?;
+            self.activate_best_chain()
+        })?;
+
+        Ok(block_hash)
+    }
+
+    /// Returns the tip of the active chain and the tips of the stale branches.
+    /// Tips are sorted by their height, in descending order.
+    pub fn get_chain_tips(&self) -> Result<json::GetChainTipsResult, LedgerError> {
+        let height = self.get_block_height()?;
+        let mut tips = vec![GetChainTipsResultTip {
+            height: height as u64,
+            hash: self.get_block_hash(height)?,
+            branch_length: 0,
+            status: GetChainTipsResultStatus::Active,
+        }];
+
+        for hash in self.get_stale_block_hashes()? {
+            let (fork_height, branch) = self.get_branch(hash)?;
+            let height = branch.last().unwrap().height;
+
+            let status = if branch.iter().any(|stale| stale.invalid) {
+                GetChainTipsResultStatus::Invalid
+            } else if branch.iter().all(|stale| stale.validated) {
+                GetChainTipsResultStatus::ValidFork
+            } else {
+                GetChainTipsResultStatus::ValidHeaders
+            };
+
+            tips.push(GetChainTipsResultTip {
+                height: height as u64,
+                hash,
+                branch_length: (height - fork_height) as usize,
+                status,
+            });
+        }
+
+        tips.sort_by_key(|tip| Reverse(tip.height));
+
+        Ok(tips)
+    }
+
+    /// Marks a block as invalid. If the block is in the active chain, it and
+    /// all of it's descendants are disconnected. After that, best valid chain
+    /// is activated.
+    ///
+    /// If any of the steps fails, ledger is left untouched.
+    #[tracing::instrument(skip(self))]
+    pub fn invalidate_block(&self, hash: BlockHash) -> Result<(), LedgerError> {
+        self.atomically(|| {
+            match self.get_block_height_with_hash(hash) {
+                Some(0) => {
+                    return Err(LedgerError::Block(
+                        "Genesis block can't be invalidated".to_string(),
+                    ))
+                }
+                Some(height) => self.set_tip(self.get_block_hash(height - 1)?)?,
+                None => {
+                    self.get_stale_block(hash)?;
+                }
+            };
+
+            self.set_block_invalidity(hash, true)?;
+
+            self.activate_best_chain()
+        })
+    }
+
+    /// Removes invalidity status of a block, it's ancestors and descendants.
+    /// After that, best valid chain is activated, which can cause a
+    /// reorganization.
+    ///
+    /// If any of the steps fails, ledger is left untouched.
+    #[tracing::instrument(skip(self))]
+    pub fn reconsider_block(&self, hash: BlockHash) -> Result<(), LedgerError> {
+        self.atomically(|| {
+            let mut ancestor = hash;
+            while self.get_block_height_with_hash(ancestor).is_none() {
+                self.set_block_invalidity(ancestor, false)?;
+                ancestor = self.get_stale_block(ancestor)?.block.header.prev_blockhash;
+            }
+
+            if let Err(e) = self.database.lock().unwrap().execute(
+                "WITH RECURSIVE descendants(hash) AS
+                (
+                    SELECT ?1
+                    UNION
+                    SELECT stale_blocks.hash FROM stale_blocks JOIN descendants ON stale_blocks.prev_hash = descendants.hash
+                )
+                UPDATE stale_blocks SET invalid = 0 WHERE hash IN descendants",
+                params![Ledger::encode_block_hash(hash)],
+            ) {
+                return Err(LedgerError::Block(format!(
+                    "Couldn't reconsider descendants of block {}: {}",
+                    hash, e
+                )));
+            };
+
+            self.activate_best_chain()
+        })
+    }
+
+    /// Activates the valid chain with the most work. If there are more than one
+    /// candidates, active chain is preferred.
+    ///
+    /// Only the stale tips are visited. A branch with an invalid block can
+    /// still be activated up to that block.
+    fn activate_best_chain(&self) -> Result<(), LedgerError> {
+        let branches = self
+            .get_stale_block_hashes()?
+            .into_iter()
+            .map(|hash| self.get_branch(hash))
+            .collect::<Result<Vec<_>, LedgerError>>()?;
+        let Some(lowest_fork_height) = branches.iter().map(|(height, _)| *height).min() else {
+            return Ok(());
+        };
+
+        // Work of the active chain after the lowest fork point, at every
+        // height. Blocks below it are common to every branch.
+        let mut chain_work = vec![Work::from_be_bytes([0; 32])];
+        for header in self.get_block_headers(lowest_fork_height + 1..=self.get_block_height()?)? {
+            chain_work.push(*chain_work.last().unwrap() + header.work());
+        }
+
+        let mut best_work = *chain_work.last().unwrap();
+        let mut best_tip = None;
+
+        for (fork_height, branch) in branches {
+            let mut work = chain_work[(fork_height - lowest_fork_height) as usize];
+
+            for stale in branch.iter().take_while(|stale| !stale.invalid) {
+                work = work + stale.block.header.work();
+
+                if work > best_work {
+                    best_work = work;
+                    best_tip = Some(stale.block.block_hash());
+                }
+            }
+        }
+
+        match best_tip {
+            Some(hash) => self.set_tip(hash),
+            None => Ok(()),
+        }
+    }
+
+    /// Makes block with `hash` the tip of the active chain. Blocks after the
+    /// fork point are disconnected and the blocks of the new branch are
+    /// connected. Transactions of the disconnected blocks are sent back to the
+    /// mempool, if they are not in the new branch.
+    ///
+    /// If any of the steps fails, ledger is left untouched.
+    #[tracing::instrument(skip(self))]
+    fn set_tip(&self, hash: BlockHash) -> Result<(), LedgerError> {
+        self.atomically(|| {
+            let (fork_height, branch) = self.get_branch(hash)?;
+            let mempool = self.detach_mempool()?;
+
+            let mut disconnected = Vec::new();
+            while self.get_block_height()? > fork_height {
+                disconnected.push(self.disconnect_tip()?);
+            }
+
+            let mut confirmed = HashSet::new();
+            for stale in branch {
+                confirmed.extend(stale.block.txdata.iter().map(|tx| tx.compute_txid()));
+                self.connect_block(&stale.block, stale.time)?;
+            }
+
+            // Transactions in the disconnected blocks are older than the ones
+            // in the mempool. Therefore they should be added first.
+            let transactions = disconnected
+                .iter()
+                .rev()
+                .flat_map(|block| block.txdata.iter().skip(1).cloned())
+                .chain(mempool)
+                .filter(|tx| !confirmed.contains(&tx.compute_txid()))
+                .collect();
+            let coinbases = disconnected
+                .iter()
+                .map(|block| block.txdata.first().unwrap().compute_txid())
+                .filter(|txid| !confirmed.contains(txid))
+                .collect();
+
+            self.attach_mempool(transactions, coinbases, true)
+        })
+    }
+
+    /// Returns the stale blocks that link the block with `hash` to the active
+    /// chain, in ascending order, alongside with the height of the fork point.
+    fn get_branch(&self, hash: BlockHash) -> Result<(u32, Vec<StaleBlock>), LedgerError> {
+        let mut branch = Vec::new();
+        let mut hash = hash;
+
+        let fork_height = loop {
+            if let Some(height) = self.get_block_height_with_hash(hash) {
+                break height;
+            }
+
+            let stale = self.get_stale_block(hash)?;
+            hash = stale.block.header.prev_blockhash;
+            branch.push(stale);
+        };
+        branch.reverse();
+
+        Ok((fork_height, branch))
+    }
+
+    /// Connects a block on top of the active chain. Inputs spent by the block
+    /// are removed from the UTXO set and outputs created by it are added.
+    fn connect_block(&self, block: &Block, time: u32) -> Result<(), LedgerError> {
+        let height = self.get_block_height()? + 1;
+
+        if self.get_previous_block_hash(&block.header)? != self.get_block_hash(height - 1)? {
+            return Err(LedgerError::Block(format!(
+                "Block {} doesn't extend the active chain",
+                block.block_hash()
+            )));
+        }
+
+        for transaction in &block.txdata {
+            // Inputs that are not known by the ledger can't be checked.
+            for input in &transaction.input {
+                let utxo = input.previous_output;
+
+                if self.get_transaction(utxo.txid).is_ok() && self.is_utxo_spent(utxo) {
+                    return Err(LedgerError::InputMissingOrSpent(utxo));
+                }
+            }
+
+            self.handle_transaction_utxos(transaction, height)?;
+            self.set_transaction_block_height(transaction, height)?;
+        }
+
+        self.insert_block(block, height, time)
+    }
+
+    /// Disconnects the tip of the active chain and moves it to the stale
+    /// blocks. Outputs created by the block are removed from the UTXO set and
+    /// the inputs spent by it are added back, using it's undo data.
+    fn disconnect_tip(&self) -> Result<Block, LedgerError> {
+        let height = self.get_block_height()?;
+        if height == 0 {
+            return Err(LedgerError::Block(
+                "Genesis block can't be disconnected".to_string(),
+            ));
+        }
+
+        let block = self.get_block_with_height(height)?;
+        let (_, time) = self.get_block_height_and_time(block.block_hash())?;
+        tracing::debug!(
+            "Disconnecting block {} at height {}",
+            block.block_hash(),
+            height
+        );
+
+        for transaction in block.txdata.iter().rev() {
+            let txid = transaction.compute_txid();
+
+            for vout in 0..(transaction.output.len() as u32) {
+                self.remove_utxo(OutPoint { txid, vout })?;
+            }
+        }
+
+        for utxo in self.get_undo_data(height)? {
+            self.add_utxo(&utxo)?;
+        }
+
+        if let Err(e) = self.database.lock().unwrap().execute_batch(&format!(
+            "DELETE FROM undo WHERE block_height = {height};
+            DELETE FROM blocks WHERE height = {height};"
+        )) {
+            return Err(LedgerError::Block(format!(
+                "Couldn't remove block at height {} from the active chain: {}",
+                height, e
+            )));
+        };
+
+        self.add_stale_block(&block, height, time, true)?;
+
+        Ok(block)
+    }
+
+    /// Removes every mempool transaction's effects from the UTXO set and
+    /// empties the mempool. Returns the removed transactions in mempool order.
+    pub(super) fn detach_mempool(&self) -> Result<Vec<Transaction>, LedgerError> {
+        let transactions = self.get_mempool_transactions();
+
+        for transaction in transactions.iter().rev() {
+            let txid = transaction.compute_txid();
+
+            for vout in 0..(transaction.output.len() as u32) {
+                self.remove_utxo(OutPoint { txid, vout })?;
+            }
+
+            for input in &transaction.input {
+                // Inputs that are not known by the ledger can't be restored.
+                if let Ok(utxo) = self.get_utxo_from_transaction(input.previous_output) {
+                    self.add_utxo(&utxo)?;
+                }
+            }
+        }
+
+        self.clean_mempool();
+
+        Ok(transactions)
+    }
+
+    /// Adds transactions back to the mempool, in given order. Transactions that
+    /// spend an unavailable UTXO or an output of a `removed` transaction are
+    /// deleted from the ledger, alongside with the `removed` ones.
+    ///
+    /// After a reorganization, chain can be shorter than before. So if
+    /// `is_reorg` is set, transactions must also be mineable in the next block.
+    /// Please check [`Ledger::can_reattach_transaction`] for the conditions.
+    pub(super) fn attach_mempool(
+        &self,
+        transactions: Vec<Transaction>,
+        removed: HashSet<Txid>,
+        is_reorg: bool,
+    ) -> Result<(), LedgerError> {
+        let mut removed = removed;
+
+        for transaction in transactions {
+            let txid = transaction.compute_txid();
+
+            if self.can_reattach_transaction(&transaction, &removed, is_reorg) {
+                self.reattach_transaction(&transaction)?;
+            } else {
+                tracing::debug!("Transaction {txid} is removed from the ledger");
+                removed.insert(txid);
+            }
+        }
+
+        for txid in removed {
+            if let Err(e) = self.database.lock().unwrap().execute(
+                "DELETE FROM transactions WHERE txid = ?1",
+                params![txid.to_string()],
+            ) {
+                return Err(LedgerError::Transaction(format!(
+                    "Couldn't remove transaction {} from ledger: {}",
+                    txid, e
+                )));
+            };
+
+            self.remove_transaction_history(txid)?;
+        }
+
+        Ok(())
+    }
+
+    /// Adds a transaction that was detached from the mempool back to it,
+    /// without any checks.
+    pub(super) fn reattach_transaction(
+        &self,
+        transaction: &Transaction,
+    ) -> Result<(), LedgerError> {
+        let height = self.get_block_height()? + 1;
+
+        self.handle_transaction_utxos(transaction, height)?;
+        self.set_transaction_block_height(transaction, height)?;
+        self.add_mempool_transaction(transaction.compute_txid())
+    }
+
+    /// Returns true if a transaction can go back to the mempool: It must not
+    /// spend an output of a `removed` transaction or an unavailable UTXO.
+    ///
+    /// If `is_reorg` is set, it must also be final in the next block, like
+    /// Bitcoin Core's `removeForReorg`. If every input is known by the ledger,
+    /// its coinbase inputs must be mature and relative lock times must be
+    /// satisfied too. Inputs that are not known by the ledger can't be checked.
+    fn can_reattach_transaction(
+        &self,
+        transaction: &Transaction,
+        removed: &HashSet<Txid>,
+        is_reorg: bool,
+    ) -> bool {
+        let mut is_known = true;
+
+        for input in &transaction.input {
+            let utxo = input.previous_output;
+
+            if removed.contains(&utxo.txid) {
+                return false;
+            }
+            if self.get_transaction(utxo.txid).is_err() {
+                is_known = false;
+            } else if self.is_utxo_spent(utxo) {
+                return false;
+            }
+        }
+
+        if !is_reorg {
+            return true;
+        }
+        if self.check_transaction_finality(transaction).is_err() {
+            return false;
+        }
+
+        !is_known
+            || (self.check_coinbase_maturity(transaction).is_ok()
+                && self.check_sequence_locks(transaction).is_ok())
+    }
+
+    /// Sets a transaction's block height. Transaction is added to the ledger
+    /// if it is not known.
+    fn set_transaction_block_height(
+        &self,
+        transaction: &Transaction,
+        height: u32,
+    ) -> Result<(), LedgerError> {
+        let txid = transaction.compute_txid();
+
+        let mut body = Vec::new();
+        if let Err(e) = transaction.consensus_encode(&mut body) {
+            return Err(LedgerError::Transaction(e.to_string()));
+        };
+
+        let is_new = {
+            let database = self.database.lock().unwrap();
+            let ret = match database.execute(
+                "UPDATE transactions SET block_height = ?2 WHERE txid = ?1",
+                params![txid.to_string(), height],
+            ) {
+                Ok(0) => database
+                    .execute(
+                        "INSERT INTO transactions (txid, block_height, body) VALUES (?1, ?2, ?3)",
+                        params![txid.to_string(), height, body],
+                    )
+                    .map(|_| true),
+                ret => ret.map(|_| false),
+            };
+
+            match ret {
+                Ok(is_new) => is_new,
+                Err(e) => {
+                    return Err(LedgerError::Transaction(format!(
+                        "Couldn't set block height of transaction {} to {}: {}",
+                        txid, height, e
+                    )))
+                }
+            }
+        };
+
+        if is_new {
+            self.add_transaction_history(transaction)?;
+        }
+
+        Ok(())
+    }
+
+    /// Saves the UTXOs spent by a block as it's undo data. Inputs that are not
+    /// known by the ledger are skipped, because they can't be restored.
+    pub fn add_undo_data(&self, height: u32, block: &Block) -> Result<(), LedgerError> {
+        for transaction in block.txdata.iter().filter(|tx| !tx.is_coinbase()) {
+            for input in &transaction.input {
+                let Ok(utxo) = self.get_utxo_from_transaction(input.previous_output) else {
+                    continue;
+                };
+
+                if let Err(e) = self.database.lock().unwrap().execute(
+                    "INSERT INTO undo (block_height, txid, vout, value, script_pubkey, height, coinbase) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    params![
+                        height,
+                        utxo.outpoint.txid.to_string(),
+                        utxo.outpoint.vout,
+                        utxo.txout.value.to_sat(),
+                        utxo.txout.script_pubkey.as_bytes(),
+                        utxo.height,
+                        utxo.coinbase
+                    ],
+                ) {
+                    return Err(LedgerError::Block(format!(
+                        "Couldn't add undo data for block at height {}: {}",
+                        height, e
+                    )));
+                };
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the UTXOs spent by the block at `height`.
+    fn get_undo_data(&self, height: u32) -> Result<Vec<Utxo>, LedgerError> {
+        let database = self.database.lock().unwrap();
+
+        let mut stmt = database
+            .prepare("SELECT txid, vout, value, script_pubkey, height, coinbase FROM undo WHERE block_height = ?1")
+            .unwrap();
+        let utxos = stmt
+            .query_map(params![height], Utxo::from_row)
+            .and_then(|rows| rows.collect::<Result<Vec<Utxo>, rusqlite::Error>>());
+
+        match utxos {
+            Ok(utxos) => Ok(utxos),
+            Err(e) => Err(LedgerError::Block(format!(
+                "Couldn't read undo data for block at height {}: {}",
+                height, e
+            ))),
+        }
+    }
+
+    /// Returns height and time of a block, which can be in either the active
+    /// chain or a stale branch.
+    pub fn get_block_height_and_time(&self, hash: BlockHash) -> Result<(u32, u32), LedgerError> {
+        if let Some(height) = self.get_block_height_with_hash(hash) {
+            return match self.database.lock().unwrap().query_row(
+                "SELECT time FROM blocks WHERE height = ?1",
+                params![height],
+                |row| row.get::<_, u32>(0),
+            ) {
+                Ok(time) => Ok((height, time)),
+                Err(e) => Err(LedgerError::Block(format!(
+                    "Couldn't read time of the block at height {}: {}",
+                    height, e
+                ))),
+            };
+        }
+
+        let stale = self.get_stale_block(hash)?;
+
+        Ok((stale.height, stale.time))
+    }
+
+    fn add_stale_block(
+        &self,
+        block: &Block,
+        height: u32,
+        time: u32,
+        validated: bool,
+    ) -> Result<(), LedgerError> {
+        Ledger::check_block_header(&block.header)?;
+
+        let mut body: Vec<u8> = Vec::new();
+        if let Err(e) = block.consensus_encode(&mut body) {
+            return Err(LedgerError::Block(format!("Couldn't encode block: {}", e)));
+        };
+
+        if let Err(e) = self.database.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO stale_blocks (hash, prev_hash, height, time, validated, invalid, body) VALUES (?1, ?2, ?3, ?4, ?5, 0, ?6)",
+            params![
+                Ledger::encode_block_hash(block.block_hash()),
+                Ledger::encode_block_hash(block.header.prev_blockhash),
+                height,
+                time,
+                validated,
+                body
+            ],
+        ) {
+            return Err(LedgerError::Block(format!(
+                "Couldn't add stale block {} to ledger: {}",
+                block.block_hash(),
+                e
+            )));
+        };
+
+        Ok(())
+    }
+
+    fn get_stale_block(&self, hash: BlockHash) -> Result<StaleBlock, LedgerError> {
+        let qr = self.database.lock().unwrap().query_row(
+            "SELECT height, time, validated, invalid, body FROM stale_blocks WHERE hash = ?1",
+            params![Ledger::encode_block_hash(hash)],
+            |row| {
+                Ok((
+                    row.get::<_, u32>(0)?,
+                    row.get::<_, u32>(1)?,
+                    row.get::<_, bool>(2)?,
+                    row.get::<_, bool>(3)?,
+                    row.get::<_, Vec<u8>>(4)?,
+                ))
+            },
+        );
+        let (height, time, validated, invalid, body) = match qr {
+            Ok(qr) => qr,
+            Err(e) => {
+                return Err(LedgerError::Block(format!(
+                    "Couldn't find any block with block hash {}: {}",
+                    hash, e
+                )))
+            }
+        };
+
+        match Block::consensus_decode(&mut body.as_slice()) {
+            Ok(block) => Ok(StaleBlock {
+                block,
+                height,
+                time,
+                validated,
+                invalid,
+            }),
+            Err(e) => Err(LedgerError::Block(format!(
+                "Internal error while reading block from ledger: {}",
+                e
+            ))),
+        }
+    }
+
+    /// Returns hashes of the stale blocks without any children, which are the
+    /// tips of the stale branches.
+    fn get_stale_block_hashes(&self) -> Result<Vec<BlockHash>, LedgerError> {
+        let database = self.database.lock().unwrap();
+
+        let mut stmt = database
+            .prepare(
+                "SELECT hash FROM stale_blocks WHERE hash NOT IN (SELECT prev_hash FROM stale_blocks)",
+            )
+            .unwrap();
+        let hashes = stmt
+            .query_map([], |row| {
+                let hash: Vec<u8> = row.get(0)?;
+
+                Ok(BlockHash::consensus_decode(&mut hash.as_slice()).unwrap())
+            })
+            .and_then(|rows| rows.collect::<Result<Vec<BlockHash>, rusqlite::Error>>());
+
+        match hashes {
+            Ok(hashes) => Ok(hashes),
+            Err(e) => Err(LedgerError::Block(format!(
+                "Couldn't read stale blocks: {}",
+                e
+            ))),
+        }
+    }
+
+    pub fn remove_stale_block(&self, hash: BlockHash) -> Result<(), LedgerError> {
+        if let Err(e) = self.database.lock().unwrap().execute(
+            "DELETE FROM stale_blocks WHERE hash = ?1",
+            params![Ledger::encode_block_hash(hash)],
+        ) {
+            return Err(LedgerError::Block(format!(
+                "Couldn't remove stale block {}: {}",
+                hash, e
+            )));
+        };
+
+        Ok(())
+    }
+
+    fn set_block_invalidity(&self, hash: BlockHash, invalid: bool) -> Result<(), LedgerError> {
+        if let Err(e) = self.database.lock().unwrap().execute(
+            "UPDATE stale_blocks SET invalid = ?2 WHERE hash = ?1",
+            params![Ledger::encode_block_hash(hash), invalid],
+        ) {
+            return Err(LedgerError::Block(format!(
+                "Couldn't update validity of block {}: {}",
+                hash, e
+            )));
+        };
+
+        Ok(())
+    }
+
+    fn encode_block_hash(hash: BlockHash) -> Vec<u8> {
+        let mut encoded_hash: Vec<u8> = Vec::new();
+        hash.consensus_encode(&mut encoded_hash).unwrap();
+
+        encoded_hash
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ledger::{self, Ledger};
+    use bitcoin::{hashes::Hash, Amount, BlockHash, OutPoint, Sequence};
+    use bitcoincore_rpc::json::GetChainTipsResultStatus;
+
+    #[test]
+    fn invalidate_and_reconsider_block() {
+        // Transaction spends a coinbase output, which should stay mature after
+        // the reorganization.
+        let ledger = Ledger::new("invalidate_and_reconsider_block?coinbase_maturity=0").unwrap();
+        let address = ledger::Ledger::generate_credential_from_witness().address;
+
+        ledger.mine_block(&address).unwrap();
+        let coinbase = ledger._get_transactions().first().unwrap().clone();
+        let utxo = OutPoint {
+            txid: coinbase.compute_txid(),
+            vout: 0,
+        };
+
+        let txout = ledger.create_txout(Amount::from_sat(0x45), address.script_pubkey());
+        let tx = ledger.create_transaction(vec![ledger.create_txin(utxo.txid, 0)], vec![txout]);
+        let txid = ledger.add_transaction_unconditionally(tx).unwrap();
+
+        let block_hash = ledger.mine_block(&address).unwrap();
+        let tip_hash = ledger.mine_block(&address).unwrap();
+        assert_eq!(ledger.get_block_height().unwrap(), 3);
+        assert_eq!(ledger.get_transaction_block_height(&txid).unwrap(), 2);
+        assert!(ledger.is_utxo_spent(utxo));
+
+        ledger.invalidate_block(block_hash).unwrap();
+        assert_eq!(ledger.get_block_height().unwrap(), 1);
+        assert!(ledger.get_mempool_transaction(txid).is_some());
+        assert_eq!(ledger.get_transaction_block_height(&txid).unwrap(), 2);
+        assert!(ledger.is_utxo_spent(utxo));
+        assert!(!ledger.is_utxo_spent(OutPoint { txid, vout: 0 }));
+        // Disconnected blocks can still be read.
+        assert!(ledger.get_block_with_hash(tip_hash).is_ok());
+
+        ledger.reconsider_block(block_hash).unwrap();
+        assert_eq!(ledger.get_block_height().unwrap(), 3);
+        assert_eq!(ledger.get_block_hash(3).unwrap(), tip_hash);
+        assert!(ledger.get_mempool_transactions().is_empty());
+        assert_eq!(
+            ledger.get_transaction_block_hash(&txid).unwrap(),
+            block_hash
+        );
+        assert!(ledger.is_utxo_spent(utxo));
+        assert!(!ledger.is_utxo_spent(OutPoint { txid, vout: 0 }));
+    }
+
+    #[test]
+    fn invalidate_block_removes_coinbase_spends() {
+        let ledger = Ledger::new("invalidate_block_removes_coinbase_spends").unwrap();
+        let address = ledger::Ledger::generate_credential_from_witness().address;
+
+        let block_hash = ledger.mine_block(&address).unwrap();
+        let coinbase = ledger._get_transactions().first().unwrap().clone();
+        let coinbase_txid = coinbase.compute_txid();
+
+        let txout = ledger.create_txout(Amount::from_sat(0x45), address.script_pubkey());
+        let tx = ledger.create_transaction(vec![ledger.create_txin(coinbase_txid, 0)], vec![txout]);
+        let txid = ledger.add_transaction_unconditionally(tx).unwrap();
+
+        let txout = ledger.create_txout(Amount::from_sat(0x1F), address.script_pubkey());
+        let child = ledger.create_transaction(vec![ledger.create_txin(txid, 0)], vec![txout]);
+        let child_txid = ledger.add_transaction_unconditionally(child).unwrap();
+
+        ledger.invalidate_block(block_hash).unwrap();
+        assert_eq!(ledger.get_block_height().unwrap(), 0);
+        assert!(ledger.get_mempool_transactions().is_empty());
+        assert!(ledger.get_transaction(coinbase_txid).is_err());
+        assert!(ledger.get_transaction(txid).is_err());
+        assert!(ledger.get_transaction(child_txid).is_err());
+        assert!(ledger.is_utxo_spent(OutPoint {
+            txid: coinbase_txid,
+            vout: 0
+        }));
+        assert!(ledger.is_utxo_spent(OutPoint {
+            txid: child_txid,
+            vout: 0
+        }));
+
+        assert!(ledger
+            .invalidate_block(ledger.get_block_hash(0).unwrap())
+            .is_err());
+    }
+
+    #[test]
+    fn invalidate_block_removes_unmineable_transactions() {
+        let ledger = Ledger::new(
+            "memory://invalidate_block_removes_unmineable_transactions?coinbase_maturity=2",
+        )
+        .unwrap();
+        let address = ledger::Ledger::generate_credential_from_witness().address;
+
+        ledger.mine_block(&address).unwrap();
+        let coinbase_txid = ledger.get_block_with_height(1).unwrap().txdata[0].compute_txid();
+        let txout = ledger.create_txout(Amount::from_sat(0x45), address.script_pubkey());
+        let parent = ledger.create_transaction(vec![], vec![txout.clone()]);
+        let parent_txid = ledger.add_transaction_unconditionally(parent).unwrap();
+        let block_hash = ledger.mine_block(&address).unwrap();
+
+        // Coinbase of block 1 is mature in block 3 and output of the parent can
+        // be spent with a relative lock time of 1 block in block 3.
+        let spends_coinbase = ledger.create_transaction(
+            vec![ledger.create_txin(coinbase_txid, 0)],
+            vec![txout.clone()],
+        );
+        let mut locked = ledger.create_transaction(
+            vec![ledger.create_txin(parent_txid, 0)],
+            vec![txout.clone()],
+        );
+        locked.input[0].sequence = Sequence::from_height(1);
+        let mut txids = vec![];
+        for tx in [spends_coinbase, locked] {
+            ledger.check_coinbase_maturity(&tx).unwrap();
+            ledger.check_sequence_locks(&tx).unwrap();
+            txids.push(ledger.add_transaction_unconditionally(tx).unwrap());
+        }
+        ledger.mine_block(&address).unwrap();
+
+        // Next block is 2 after the reorganization. Coinbase spend is immature
+        // and the parent is back in the mempool, so both of them are removed.
+        ledger.invalidate_block(block_hash).unwrap();
+        assert_eq!(ledger.get_block_height().unwrap(), 1);
+        assert!(ledger.get_mempool_transaction(parent_txid).is_some());
+        for txid in txids {
+            assert!(ledger.get_mempool_transaction(txid).is_none());
+            assert!(ledger.get_transaction(txid).is_err());
+        }
+        assert!(!ledger.is_utxo_spent(OutPoint {
+            txid: coinbase_txid,
+            vout: 0
+        }));
+    }
+
+    #[test]
+    fn reorg_to_longer_chain_after_reconsider() {
+        let ledger = Ledger::new("reorg_to_longer_chain_after_reconsider").unwrap();
+        let address = ledger::Ledger::generate_credential_from_witness().address;
+
+        ledger.mine_block(&address).unwrap();
+        let tx = ledger.create_transaction(
+            vec![],
+            vec![ledger.create_txout(Amount::from_sat(0x45), address.script_pubkey())],
+        );
+        let txid = ledger.add_transaction_unconditionally(tx).unwrap();
+        let block_hash = ledger.mine_block(&address).unwrap();
+        let tip_hash = ledger.mine_block(&address).unwrap();
+
+        // Mine a shorter competing chain, which also includes another
+        // transaction.
+        ledger.invalidate_block(block_hash).unwrap();
+        let tx = ledger.create_transaction(
+            vec![],
+            vec![ledger.create_txout(Amount::from_sat(0x1F), address.script_pubkey())],
+        );
+        let competing_txid = ledger.add_transaction_unconditionally(tx).unwrap();
+        let competing_hash = ledger.mine_block(&address).unwrap();
+        assert_eq!(ledger.get_block_height().unwrap(), 2);
+        assert_eq!(
+            ledger.get_transaction_block_hash(&txid).unwrap(),
+            competing_hash
+        );
+
+        ledger.reconsider_block(block_hash).unwrap();
+        assert_eq!(ledger.get_block_height().unwrap(), 3);
+        assert_eq!(ledger.get_block_hash(3).unwrap(), tip_hash);
+        assert_eq!(
+            ledger.get_transaction_block_hash(&txid).unwrap(),
+            block_hash
+        );
+        assert!(!ledger.is_utxo_spent(OutPoint { txid, vout: 0 }));
+        // Transaction that is only in the competing chain goes back to the
+        // mempool.
+        assert_eq!(ledger.get_mempool_transactions().len(), 1);
+        assert!(ledger.get_mempool_transaction(competing_txid).is_some());
+        assert!(!ledger.is_utxo_spent(OutPoint {
+            txid: competing_txid,
+            vout: 0
+        }));
+    }
+
+    #[test]
+    fn mine_on_stale_branch() {
+        let ledger = Ledger::new("mine_on_stale_branch").unwrap();
+        let address = ledger::Ledger::generate_credential_from_witness().address;
+
+        let fork_point = ledger.mine_block(&address).unwrap();
+        let tx = ledger.create_transaction(
+            vec![],
+            vec![ledger.create_txout(Amount::from_sat(0x45), address.script_pubkey())],
+        );
+        let txid = ledger.add_transaction_unconditionally(tx).unwrap();
+        let tip_hash = ledger.mine_block(&address).unwrap();
+
+        // Same work with the active chain won't cause a reorganization.
+        let stale_hash = ledger.mine_block_on(fork_point, &address).unwrap();
+        assert_ne!(stale_hash, tip_hash);
+        assert_eq!(ledger.get_block_hash(2).unwrap(), tip_hash);
+
+        let new_tip_hash = ledger.mine_block_on(stale_hash, &address).unwrap();
+        assert_eq!(ledger.get_block_height().unwrap(), 3);
+        assert_eq!(ledger.get_block_hash(3).unwrap(), new_tip_hash);
+        assert!(ledger.get_mempool_transaction(txid).is_some());
+
+        // Invalidating the new branch causes a reorganization back.
+        ledger.invalidate_block(stale_hash).unwrap();
+        assert_eq!(ledger.get_block_height().unwrap(), 2);
+        assert_eq!(ledger.get_block_hash(2).unwrap(), tip_hash);
+        assert!(ledger.get_mempool_transactions().is_empty());
+
+        let tips = ledger.get_chain_tips().unwrap();
+        assert_eq!(tips.len(), 2);
+        assert_eq!(tips[0].hash, new_tip_hash);
+        assert_eq!(tips[0].status, GetChainTipsResultStatus::Invalid);
+        assert_eq!(tips[0].branch_length, 2);
+        assert_eq!(tips[1].hash, tip_hash);
+        assert_eq!(tips[1].status, GetChainTipsResultStatus::Active);
+
+        assert!(ledger
+            .mine_block_on(BlockHash::from_byte_array([0x45; 32]), &address)
+            .is_err());
+    }
+
+    #[test]
+    fn activate_valid_part_of_invalid_branch() {
+        let ledger = Ledger::new("memory://activate_valid_part_of_invalid_branch").unwrap();
+        let address = ledger::Ledger::generate_credential_from_witness().address;
+
+        let genesis = ledger.get_block_hash(0).unwrap();
+        let first = ledger.mine_block(&address).unwrap();
+        for _ in 0..3 {
+            ledger.mine_block(&address).unwrap();
+        }
+
+        // A shorter branch, which has an invalid tip. It pays to another
+        // address, so that it's coinbases are different.
+        let other = ledger::Ledger::generate_credential_from_witness().address;
+        let mut branch = vec![genesis];
+        for _ in 0..3 {
+            branch.push(
+                ledger
+                    .mine_block_on(*branch.last().unwrap(), &other)
+                    .unwrap(),
+            );
+        }
+        ledger.invalidate_block(branch[3]).unwrap();
+        assert_eq!(ledger.get_block_height().unwrap(), 4);
+
+        // Valid part of that branch has more work than what is left from the
+        // active chain.
+        ledger.invalidate_block(first).unwrap();
+        assert_eq!(ledger.get_block_height().unwrap(), 2);
+        assert_eq!(ledger.get_block_hash(2).unwrap(), branch[2]);
+    }
+}
@@ -0,0 +1,692 @@
+//! # Database Schema
+//!
+//! Ledger database's schema is versioned. Version of a database is kept in the
+//! `schema_version` table. When a database is opened, it is upgraded to the
+//! latest version by running the migrations it is missing, in order.
+//!
+//! Databases created before versioning don't have a `schema_version` table.
+//! Those are treated as version 1.
+
+use super::{errors::LedgerError, settings, utxo::Utxo, Ledger};
+use bitcoin::{
+    consensus::{serialize, Decodable},
+    constants::genesis_block,
+    Network, OutPoint, ScriptBuf, Transaction, Txid,
+};
+use rusqlite::{params, types::Type, Connection, OptionalExtension};
+use std::{collections::HashMap, str::FromStr};
+
+/// A schema migration, which upgrades the database by a single version.
+type Migration = fn(&Connection) -> Result<(), rusqlite::Error>;
+
+/// This is where all the ledger data is kept. Note that it is not aimed to
+/// hold all kind of information about the blockchain. Just holds enough data to
+/// provide a persistent storage for the limited features that the library
+/// provides.
+///
+/// Schema migrations are kept in order: `MIGRATIONS[i]` upgrades a database from
+/// version `i` to `i + 1`. New migrations should only be appended to this
+/// list; Existing ones must never be changed.
+const MIGRATIONS: [Migration; 6] = [
+    initial_schema,
+    add_reorgs,
+    add_utxo_details,
+    add_script_history,
+    add_settings,
+    add_genesis_block,
+];
+
+/// Version 1: Initial schema.
+fn initial_schema(database: &Connection) -> Result<(), rusqlite::Error> {
+    database.execute_batch(
+        "
+    CREATE TABLE blocks
+    (
+        height    INTEGER  NOT NULL,
+        time      INTEGER  NOT NULL,
+        hash      TEXT     NOT NULL,
+        coinbase  TEXT     NOT NULL,
+        body      BLOB     NOT NULL
+
+        CONSTRAINT height PRIMARY KEY
+    );
+    INSERT INTO blocks (height, time, hash, coinbase, body) VALUES (0, 500000000, '00000000000000000000', 0, 0);
+
+    CREATE TABLE mempool
+    (
+        txid  TEXT  NOT NULL
+
+        CONSTRAINT txid PRIMARY KEY
+    );
+
+    CREATE TABLE transactions
+    (
+        txid          TEXT     NOT NULL,
+        block_height  INTEGER  NOT NULL,
+        body          BLOB     NOT NULL
+
+        CONSTRAINT txid PRIMARY KEY
+    );
+
+    CREATE TABLE utxos
+    (
+        txid          TEXT     NOT NULL,
+        vout          INTEGER  NOT NULL
+    );
+    ",
+    )
+}
+
+/// Version 2: Reorgs and snapshots.
+fn add_reorgs(database: &Connection) -> Result<(), rusqlite::Error> {
+    database.execute_batch(
+        "
+    CREATE TABLE undo
+    (
+        block_height  INTEGER  NOT NULL,
+        txid          TEXT     NOT NULL,
+        vout          INTEGER  NOT NULL
+    );
+
+    CREATE TABLE stale_blocks
+    (
+        hash       BLOB     NOT NULL  PRIMARY KEY,
+        prev_hash  BLOB     NOT NULL,
+        height     INTEGER  NOT NULL,
+        time       INTEGER  NOT NULL,
+        validated  INTEGER  NOT NULL,
+        invalid    INTEGER  NOT NULL,
+        body       BLOB     NOT NULL
+    );
+
+    CREATE TABLE snapshots
+    (
+        id  INTEGER  PRIMARY KEY  AUTOINCREMENT
+    );
+    ",
+    )
+}
+
+/// Version 3: UTXOs and undo data hold the spent output's value, script,
+/// creation height and coinbase flag. Existing entries, including the ones in
+/// snapshots, are filled using the transactions they belong to.
+fn add_utxo_details(database: &Connection) -> Result<(), rusqlite::Error> {
+    for prefix in get_table_prefixes(database)? {
+        for table in [format!("{prefix}utxos"), format!("{prefix}undo")] {
+            database.execute_batch(&format!(
+                "
+                ALTER TABLE {table} ADD COLUMN value          INTEGER  NOT NULL  DEFAULT 0;
+                ALTER TABLE {table} ADD COLUMN script_pubkey  BLOB     NOT NULL  DEFAULT x'';
+                ALTER TABLE {table} ADD COLUMN height         INTEGER  NOT NULL  DEFAULT 0;
+                ALTER TABLE {table} ADD COLUMN coinbase       INTEGER  NOT NULL  DEFAULT 0;
+                "
+            ))?;
+
+            let outpoints = database
+                .prepare(&format!("SELECT rowid, txid, vout FROM {table}"))?
+                .query_map([], |row| {
+                    let txid: String = row.get(1)?;
+
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        OutPoint {
+                            txid: parse_txid(&txid, 1)?,
+                            vout: row.get(2)?,
+                        },
+                    ))
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+
+            for (rowid, outpoint) in outpoints {
+                let transaction = database
+                    .query_row(
+                        &format!(
+                            "SELECT body, block_height FROM {prefix}transactions WHERE txid = ?1"
+                        ),
+                        params![outpoint.txid.to_string()],
+                        |row| {
+                            let body = row.get::<_, Vec<u8>>(0)?;
+
+                            Ok((decode_transaction(&body, 0)?, row.get::<_, u32>(1)?))
+                        },
+                    )
+                    .optional()?;
+
+                // Outputs of unknown transactions are left as is.
+                let Some(utxo) = transaction.and_then(|(transaction, height)| {
+                    Utxo::from_transaction(&transaction, outpoint.vout, height)
+                }) else {
+                    continue;
+                };
+
+                database.execute(
+                    &format!("UPDATE {table} SET value = ?2, script_pubkey = ?3, height = ?4, coinbase = ?5 WHERE rowid = ?1"),
+                    params![
+                        rowid,
+                        utxo.txout.value.to_sat(),
+                        utxo.txout.script_pubkey.as_bytes(),
+                        utxo.height,
+                        utxo.coinbase
+                    ],
+                )?;
+            }
+        }
+    }
+
+    database.execute_batch(
+        "
+        CREATE UNIQUE INDEX utxos_outpoint ON utxos (txid, vout);
+        CREATE INDEX utxos_script_pubkey ON utxos (script_pubkey);
+        CREATE INDEX utxos_height ON utxos (height);
+        CREATE INDEX undo_block_height ON undo (block_height);
+        ",
+    )
+}
+
+/// Version 4: Script history index. Existing transactions, including the ones
+/// in snapshots, are indexed.
+fn add_script_history(database: &Connection) -> Result<(), rusqlite::Error> {
+    for prefix in get_table_prefixes(database)? {
+        database.execute_batch(&format!(
+            "
+            CREATE TABLE {prefix}history
+            (
+                script_pubkey  BLOB  NOT NULL,
+                txid           TEXT  NOT NULL,
+
+                UNIQUE (script_pubkey, txid)
+            );
+            "
+        ))?;
+
+        let transactions = database
+            .prepare(&format!(
+                "SELECT body FROM {prefix}transactions ORDER BY block_height, rowid"
+            ))?
+            .query_map([], |row| {
+                let body = row.get::<_, Vec<u8>>(0)?;
+
+                decode_transaction(&body, 0)
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        let outputs: HashMap<Txid, &Transaction> = transactions
+            .iter()
+            .map(|transaction| (transaction.compute_txid(), transaction))
+            .collect();
+
+        for transaction in &transactions {
+            let mut scripts: Vec<&ScriptBuf> = transaction
+                .output
+                .iter()
+                .map(|output| &output.script_pubkey)
+                .collect();
+            if !transaction.is_coinbase() {
+                scripts.extend(transaction.input.iter().filter_map(|input| {
+                    outputs
+                        .get(&input.previous_output.txid)
+                        .and_then(|prev| prev.output.get(input.previous_output.vout as usize))
+                        .map(|output| &output.script_pubkey)
+                }));
+            }
+
+            for script in scripts {
+                database.execute(
+                    &format!(
+                        "INSERT OR IGNORE INTO {prefix}history (script_pubkey, txid) VALUES (?1, ?2)"
+                    ),
+                    params![script.as_bytes(), transaction.compute_txid().to_string()],
+                )?;
+            }
+        }
+    }
+
+    database.execute_batch("CREATE INDEX history_txid ON history (txid);")
+}
+
+/// Version 5: Ledger settings.
+fn add_settings(database: &Connection) -> Result<(), rusqlite::Error> {
+    for prefix in get_table_prefixes(database)? {
+        database.execute_batch(&format!(
+            "
+            CREATE TABLE {prefix}settings
+            (
+                name   TEXT     NOT NULL  PRIMARY KEY,
+                value  INTEGER  NOT NULL
+            );
+            "
+        ))?;
+    }
+
+    Ok(())
+}
+
+/// Version 6: Placeholder block at height 0 is replaced with the real genesis
+/// block of the ledger's network, including the ones in snapshots.
+///
+/// Blocks that are mined on top of the placeholder keep pointing to an all zero
+/// previous block hash. Those ledgers get the `legacy_genesis` setting, so that
+/// all zero hash keeps referring to the block at height 0.
+fn add_genesis_block(database: &Connection) -> Result<(), rusqlite::Error> {
+    for prefix in get_table_prefixes(database)? {
+        let block_count = database.query_row(
+            &format!(
+                "SELECT (SELECT COUNT(*) FROM {prefix}blocks WHERE height > 0) + (SELECT COUNT(*) FROM {prefix}stale_blocks)"
+            ),
+            params![],
+            |row| row.get::<_, i64>(0),
+        )?;
+        if block_count > 0 {
+            database.execute(
+                &format!("INSERT OR REPLACE INTO {prefix}settings (name, value) VALUES (?1, 1)"),
+                params![settings::LEGACY_GENESIS],
+            )?;
+        }
+
+        let network = database
+            .query_row(
+                &format!("SELECT value FROM {prefix}settings WHERE name = ?1"),
+                params![settings::NETWORK],
+                |row| row.get::<_, i64>(0),
+            )
+            .optional()?
+            .and_then(Ledger::decode_network)
+            .unwrap_or(Network::Regtest);
+
+        let genesis = genesis_block(network);
+        database.execute(
+            &format!("UPDATE {prefix}blocks SET time = ?1, hash = ?2, coinbase = ?3, body = ?4 WHERE height = 0"),
+            params![
+                genesis.header.time,
+                serialize(&genesis.block_hash()),
+                genesis.txdata[0].compute_txid().to_string(),
+                serialize(&genesis)
+            ],
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Parses a TXID that is read from the `column` of a row. Invalid ones are
+/// reported as a conversion failure, so that migration fails with an error.
+fn parse_txid(txid: &str, column: usize) -> Result<Txid, rusqlite::Error> {
+    Txid::from_str(txid)
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(column, Type::Text, Box::new(e)))
+}
+
+/// Decodes a transaction that is read from the `column` of a row. Invalid ones
+/// are reported as a conversion failure, so that migration fails with an error.
+fn decode_transaction(body: &[u8], column: usize) -> Result<Transaction, rusqlite::Error> {
+    Transaction::consensus_decode(&mut &body[..])
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(column, Type::Blob, Box::new(e)))
+}
+
+/// Returns the prefixes of the ledger tables: An empty one for the ledger
+/// itself and one for each snapshot.
+fn get_table_prefixes(database: &Connection) -> Result<Vec<String>, rusqlite::Error> {
+    let mut prefixes = vec![String::new()];
+
+    for table in Ledger::get_snapshot_tables(database)? {
+        // Every snapshot has exactly one mempool table.
+        if let Some(prefix) = table.strip_suffix("mempool") {
+            prefixes.push(prefix.to_owned());
+        }
+    }
+
+    Ok(prefixes)
+}
+
+/// Latest schema version, which this version of the library uses.
+pub const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+impl Ledger {
+    /// Upgrades the database to the latest schema version. An empty database
+    /// is initialized from scratch.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if database is created by a newer version of this
+    /// library or it isn't a ledger database at all.
+    pub fn migrate(database: &Connection) -> Result<(), LedgerError> {
+        let version = Ledger::get_schema_version(database)?;
+
+        if version > SCHEMA_VERSION {
+            return Err(LedgerError::Schema(format!(
+                "Database schema version {} is newer than the supported version {}; Please upgrade bitcoin-mock-rpc",
+                version, SCHEMA_VERSION
+            )));
+        }
+        if version == SCHEMA_VERSION {
+            return Ok(());
+        }
+
+        let tx = match database.unchecked_transaction() {
+            Ok(tx) => tx,
+            Err(e) => {
+                return Err(LedgerError::Database(format!(
+                    "Couldn't start migration transaction: {}",
+                    e
+                )))
+            }
+        };
+
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            if let Err(e) = migration(&tx) {
+                return Err(LedgerError::Schema(format!(
+                    "Couldn't migrate database schema from version {} to {}: {}",
+                    i,
+                    i + 1,
+                    e
+                )));
+            }
+        }
+
+        if let Err(e) = tx
+            .execute_batch(
+                "CREATE TABLE IF NOT EXISTS schema_version (version INTEGER NOT NULL); DELETE FROM schema_version;",
+            )
+            .and_then(|_| {
+                tx.execute(
+                    "INSERT INTO schema_version (version) VALUES (?1)",
+                    params![SCHEMA_VERSION],
+                )
+            })
+            .and_then(|_| tx.commit())
+        {
+            return Err(LedgerError::Database(format!(
+                "Couldn't save database schema version: {}",
+                e
+            )));
+        }
+        tracing::debug!("Database schema is migrated from version {version} to {SCHEMA_VERSION}");
+
+        Ok(())
+    }
+
+    /// Returns schema version of the database. Version is 0 for an empty
+    /// database and 1 for databases created before versioning.
+    pub fn get_schema_version(database: &Connection) -> Result<u32, LedgerError> {
+        let has_table = |name: &str| {
+            database
+                .query_row(
+                    "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?1",
+                    params![name],
+                    |row| row.get::<_, u32>(0),
+                )
+                .map(|count| count > 0)
+                .map_err(|e| LedgerError::Database(format!("Couldn't read database tables: {}", e)))
+        };
+
+        if has_table("schema_version")? {
+            return database
+                .query_row("SELECT version FROM schema_version", params![], |row| {
+                    row.get(0)
+                })
+                .map_err(|e| {
+                    LedgerError::Schema(format!("Couldn't read database schema version: {}", e))
+                });
+        }
+
+        if has_table("blocks")? {
+            return Ok(1);
+        }
+
+        let table_count = database
+            .query_row(
+                "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite\\_%' ESCAPE '\\'",
+                params![],
+                |row| row.get::<_, u32>(0),
+            )
+            .map_err(|e| LedgerError::Database(format!("Couldn't read database tables: {}", e)))?;
+        if table_count != 0 {
+            return Err(LedgerError::Schema(
+                "Database is not empty but it's not a ledger database either".to_owned(),
+            ));
+        }
+
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger::{CoinbaseOptions, Database};
+    use bitcoin::{
+        absolute, consensus::serialize, hashes::Hash, transaction, Amount, BlockHash, ScriptBuf,
+        TxOut,
+    };
+    use std::sync::Arc;
+
+    #[test]
+    fn migrate_empty_database() {
+        let database = Connection::open_in_memory().unwrap();
+        assert_eq!(Ledger::get_schema_version(&database).unwrap(), 0);
+
+        Ledger::migrate(&database).unwrap();
+        assert_eq!(
+            Ledger::get_schema_version(&database).unwrap(),
+            SCHEMA_VERSION
+        );
+
+        // Migrating again shouldn't change anything.
+        Ledger::migrate(&database).unwrap();
+        assert_eq!(
+            Ledger::get_schema_version(&database).unwrap(),
+            SCHEMA_VERSION
+        );
+    }
+
+    #[test]
+    fn migrate_unversioned_database() {
+        let database = Connection::open_in_memory().unwrap();
+        MIGRATIONS[0](&database).unwrap();
+        assert_eq!(Ledger::get_schema_version(&database).unwrap(), 1);
+
+        // Version 1 UTXOs only have an outpoint.
+        let txout = TxOut {
+            value: Amount::from_sat(0x45),
+            script_pubkey: ScriptBuf::from_bytes(vec![0x51]),
+        };
+        let transaction = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![],
+            output: vec![txout.clone()],
+        };
+        let outpoint = OutPoint {
+            txid: transaction.compute_txid(),
+            vout: 0,
+        };
+        database
+            .execute(
+                "INSERT INTO transactions (txid, block_height, body) VALUES (?1, 1, ?2)",
+                params![outpoint.txid.to_string(), serialize(&transaction)],
+            )
+            .unwrap();
+        database
+            .execute(
+                "INSERT INTO utxos (txid, vout) VALUES (?1, 0)",
+                params![outpoint.txid.to_string()],
+            )
+            .unwrap();
+
+        Ledger::migrate(&database).unwrap();
+        assert_eq!(
+            Ledger::get_schema_version(&database).unwrap(),
+            SCHEMA_VERSION
+        );
+
+        let ledger = Ledger {
+            database: Arc::new(Database::new(database)),
+        };
+        assert_eq!(
+            ledger.get_block_hash(0).unwrap(),
+            genesis_block(Network::Regtest).block_hash()
+        );
+        let utxo = ledger.get_utxo(outpoint).unwrap();
+        assert_eq!(utxo.txout, txout);
+        assert_eq!(utxo.height, 1);
+        assert!(!utxo.coinbase);
+        assert_eq!(
+            ledger.get_history(&txout.script_pubkey).unwrap()[0].txid,
+            outpoint.txid
+        );
+
+        let address = Ledger::generate_credential_from_witness().address;
+        ledger.mine_block(&address).unwrap();
+        ledger.snapshot().unwrap();
+        assert_eq!(ledger.get_block_height().unwrap(), 1);
+    }
+
+    #[test]
+    fn migrate_database_with_blocks() {
+        let database = Connection::open_in_memory().unwrap();
+        MIGRATIONS[0](&database).unwrap();
+
+        // Block 1 is mined on top of the placeholder genesis block, which has an
+        // all zero hash.
+        let builder = Ledger::new("memory://migrate_database_with_blocks").unwrap();
+        let address = Ledger::generate_credential_from_witness().address;
+        let coinbase = builder
+            .create_coinbase_transaction(
+                &address,
+                vec![],
+                1,
+                Amount::ZERO,
+                &CoinbaseOptions::default(),
+            )
+            .unwrap();
+        let block = builder
+            .build_block(
+                BlockHash::all_zeros(),
+                1_700_000_000,
+                vec![coinbase.clone()],
+            )
+            .unwrap();
+        let block_hash = block.block_hash();
+        let txid = coinbase.compute_txid();
+
+        database
+            .execute(
+                "INSERT INTO blocks (height, time, hash, coinbase, body) VALUES (1, ?1, ?2, ?3, ?4)",
+                params![
+                    block.header.time,
+                    serialize(&block_hash),
+                    txid.to_string(),
+                    serialize(&block)
+                ],
+            )
+            .unwrap();
+        database
+            .execute(
+                "INSERT INTO transactions (txid, block_height, body) VALUES (?1, 1, ?2)",
+                params![txid.to_string(), serialize(&coinbase)],
+            )
+            .unwrap();
+        for vout in 0..coinbase.output.len() as u32 {
+            database
+                .execute(
+                    "INSERT INTO utxos (txid, vout) VALUES (?1, ?2)",
+                    params![txid.to_string(), vout],
+                )
+                .unwrap();
+        }
+
+        Ledger::migrate(&database).unwrap();
+        let ledger = Ledger {
+            database: Arc::new(Database::new(database)),
+        };
+        let genesis_hash = genesis_block(Network::Regtest).block_hash();
+
+        // All zero hash keeps referring to the genesis block.
+        assert_eq!(ledger.get_block_height().unwrap(), 1);
+        assert_eq!(
+            ledger.get_block_height_with_hash(BlockHash::all_zeros()),
+            Some(0)
+        );
+        assert_eq!(
+            ledger.get_previous_block_hash(&block.header).unwrap(),
+            genesis_hash
+        );
+
+        ledger.invalidate_block(block_hash).unwrap();
+        assert_eq!(ledger.get_block_height().unwrap(), 0);
+        assert_eq!(ledger.get_block_hash(0).unwrap(), genesis_hash);
+
+        ledger.reconsider_block(block_hash).unwrap();
+        assert_eq!(ledger.get_block_height().unwrap(), 1);
+        assert_eq!(ledger.get_block_hash(1).unwrap(), block_hash);
+        assert!(ledger.get_utxo(OutPoint { txid, vout: 0 }).is_ok());
+
+        ledger.mine_block(&address).unwrap();
+        assert_eq!(ledger.get_block_height().unwrap(), 2);
+    }
+
+    #[test]
+    fn reject_corrupt_database() {
+        let corrupt_transaction = Connection::open_in_memory().unwrap();
+        MIGRATIONS[0](&corrupt_transaction).unwrap();
+        let txid = Txid::all_zeros().to_string();
+        corrupt_transaction
+            .execute(
+                "INSERT INTO transactions (txid, block_height, body) VALUES (?1, 1, x'45')",
+                params![txid],
+            )
+            .unwrap();
+        corrupt_transaction
+            .execute(
+                "INSERT INTO utxos (txid, vout) VALUES (?1, 0)",
+                params![txid],
+            )
+            .unwrap();
+
+        let corrupt_txid = Connection::open_in_memory().unwrap();
+        MIGRATIONS[0](&corrupt_txid).unwrap();
+        corrupt_txid
+            .execute(
+                "INSERT INTO utxos (txid, vout) VALUES ('foo', 0)",
+                params![],
+            )
+            .unwrap();
+
+        for database in [corrupt_transaction, corrupt_txid] {
+            assert!(matches!(
+                Ledger::migrate(&database),
+                Err(LedgerError::Schema(_))
+            ));
+            // Nothing is migrated.
+            assert_eq!(Ledger::get_schema_version(&database).unwrap(), 1);
+        }
+    }
+
+    #[test]
+    fn reject_newer_database() {
+        let database = Connection::open_in_memory().unwrap();
+        Ledger::migrate(&database).unwrap();
+        database
+            .execute(
+                "UPDATE schema_version SET version = ?1",
+                params![SCHEMA_VERSION + 1],
+            )
+            .unwrap();
+
+        assert!(matches!(
+            Ledger::migrate(&database),
+            Err(LedgerError::Schema(_))
+        ));
+    }
+
+    #[test]
+    fn reject_unknown_database() {
+        let database = Connection::open_in_memory().unwrap();
+        database
+            .execute_batch("CREATE TABLE foo (bar INTEGER)")
+            .unwrap();
+
+        assert!(matches!(
+            Ledger::migrate(&database),
+            Err(LedgerError::Schema(_))
+        ));
+    }
+}
@@ -1,13 +1,20 @@
 //! # Script Related Ledger Operations
+//!
+//! Relative lock times of BIP68 are enforced for every input of a version 2
+//! transaction, regardless of its script. OP_CSV is evaluated by the script
+//! executor against the input's sequence, like consensus does. So it can be
+//! anywhere in a script.
 
 use super::{errors::LedgerError, Ledger};
-use bitcoin::{
-    opcodes::all::{OP_CSV, OP_PUSHNUM_1},
-    relative, script, OutPoint, ScriptBuf, Sequence,
-};
+use bitcoin::{relative, transaction, ScriptBuf, Transaction};
 use bitcoin_scriptexec::{Exec, ExecCtx, Options, TxTemplate};
 
 impl Ledger {
+    /// Executes `script_buf` for an input of `tx_template`. OP_CLTV compares
+    /// its argument against the transaction's lock time, which is only
+    /// reached by the chain because transactions must be final before they
+    /// are accepted. Similarly, OP_CSV compares its argument against the
+    /// input's sequence, which is enforced by [`Ledger::check_sequence_locks`].
     pub fn run_script(
         &self,
         ctx: ExecCtx,
@@ -15,12 +22,6 @@ impl Ledger {
         script_buf: ScriptBuf,
         script_witness: Vec<Vec<u8>>,
     ) -> Result<(), LedgerError> {
-        self.check_sequence(
-            tx_template.tx.input[tx_template.input_idx].previous_output,
-            script_buf.clone(),
-            tx_template.tx.input[tx_template.input_idx].sequence.0,
-        )?;
-
         let mut exec = Exec::new(
             ctx,
             Options::default(),
@@ -48,105 +49,62 @@ impl Ledger {
         Ok(())
     }
 
-    #[inline]
-    pub fn sequence_to_timelock(sequence: u32) -> Result<relative::LockTime, LedgerError> {
-        match relative::LockTime::from_sequence(Sequence::from_consensus(sequence)) {
-            Ok(lt) => Ok(lt),
-            Err(e) => Err(LedgerError::Script(format!(
-                "Couldn't convert sequence {} to timelock: {}",
-                sequence, e
-            ))),
-        }
-    }
-
-    /// Checks if a script is a CSV script. If it is, returns lock time.
-    fn is_csv(script_buf: ScriptBuf) -> Option<u32> {
-        let mut instructions = script_buf.instructions();
-        let op1 = instructions.next();
-        let op2 = instructions.next();
-
-        if let (Some(Ok(op1)), Some(Ok(op2))) = (op1, op2) {
-            tracing::trace!("First 2 OP in script are: {:?} and {:?}", op1, op2);
-
-            if op2 == script::Instruction::Op(OP_CSV) {
-                let op1_data: i64;
-
-                if let Some(bytes) = op1.push_bytes() {
-                    op1_data =
-                        bitcoin_scriptexec::utils::read_scriptint_size(bytes.as_bytes(), 5, true)
-                            .unwrap();
-                } else {
-                    let data = op1.opcode().unwrap().to_u8();
-                    let data = data - (OP_PUSHNUM_1.to_u8() - 1);
-                    op1_data = data as i64;
-                };
-
-                tracing::debug!("OP_CSV argument: {}", op1_data);
-
-                return Some(op1_data as u32);
-            }
+    /// Checks BIP68 relative lock times of the inputs, for the next block.
+    /// Only version 2 transactions have them and an input can disable its
+    /// lock with the disable flag of its sequence.
+    ///
+    /// Height based locks count the blocks since the spent output's block.
+    /// Time based locks are compared between the median time past of the tip
+    /// and the median time past of the block before the spent output's block.
+    /// Outputs of the mempool transactions are treated as if they are in the
+    /// next block.
+    #[tracing::instrument(skip_all)]
+    pub fn check_sequence_locks(&self, transaction: &Transaction) -> Result<(), LedgerError> {
+        if transaction.version < transaction::Version::TWO {
+            return Ok(());
         }
 
-        None
-    }
-
-    /// Checks if it is a CSV script and compares sequence against the current
-    /// block height/time.
-    #[tracing::instrument(skip(self))]
-    fn check_sequence(
-        &self,
-        utxo: OutPoint,
-        script_buf: ScriptBuf,
-        input_sequence: u32,
-    ) -> Result<(), LedgerError> {
-        // If not a CSV script, we don't need to check sequence.
-        match Ledger::is_csv(script_buf) {
-            Some(_) => (),
-            None => return Ok(()),
-        };
-        tracing::trace!("A CSV script found, checking sequence...");
-
-        let current_block_height = self.get_block_height()?;
-        let current_block_time = self.get_block_time(current_block_height)?;
-
-        let tx_block_height = self.get_transaction_block_height(&utxo.txid)?;
-        let tx_block_time = self.get_block_time(tx_block_height)?;
-
-        let blocks_after = current_block_height - tx_block_height;
-        let time_after = current_block_time - tx_block_time;
-        tracing::debug!(
-            "After the transaction mined, number of blocks are mined: {}, time passed: {}",
-            blocks_after,
-            time_after
-        );
-
-        let sequence_lock = Ledger::sequence_to_timelock(input_sequence)?;
-        tracing::debug!("Sequence lock time: {:?}", sequence_lock);
-
-        match sequence_lock {
-            relative::LockTime::Blocks(height) => {
-                if height.value() - 1 > blocks_after as u16 {
-                    return Err(LedgerError::Script(format!(
-                        "Input {:?} is locked until block {} (current block height {})",
-                        utxo,
-                        tx_block_height + height.value() as u32,
-                        current_block_height,
-                    )));
+        let next_height = self.get_block_height()? + 1;
+        let median_time_past = self.get_median_time_past(self.get_block_hash(next_height - 1)?)?;
+
+        for input in &transaction.input {
+            let Some(lock_time) = input.sequence.to_relative_lock_time() else {
+                continue;
+            };
+            let utxo = input.previous_output;
+            let utxo_height = self.get_transaction_block_height(&utxo.txid)?;
+            tracing::trace!("Input {utxo} at height {utxo_height} has lock time {lock_time}");
+
+            match lock_time {
+                relative::LockTime::Blocks(height) => {
+                    let min_height = utxo_height.saturating_add(height.value() as u32);
+
+                    if next_height < min_height {
+                        return Err(LedgerError::Script(format!(
+                            "Input {} is locked until block {} (next block height {})",
+                            utxo, min_height, next_height
+                        )));
+                    }
                 }
-            }
-            relative::LockTime::Time(time) => {
-                if time.value() - (10 * 60) > time_after as u16 {
-                    return Err(LedgerError::Script(format!(
-                        "Input {:?} is locked until time {} (current block time {})",
-                        utxo,
-                        tx_block_time + time.value() as u32,
-                        current_block_time
-                    )));
+                relative::LockTime::Time(time) => {
+                    let Some(prev_height) = utxo_height.checked_sub(1) else {
+                        return Err(LedgerError::Script(format!(
+                            "Input {} has a time based lock, but its output is at height 0",
+                            utxo
+                        )));
+                    };
+                    let utxo_time = self.get_median_time_past(self.get_block_hash(prev_height)?)?;
+                    let min_time = utxo_time.saturating_add(time.value() as u32 * 512);
+
+                    if median_time_past < min_time {
+                        return Err(LedgerError::Script(format!(
+                            "Input {} is locked until median time past {} (current median time past {})",
+                            utxo, min_time, median_time_past
+                        )));
+                    }
                 }
-            }
-        };
-
-        tracing::trace!("Lock time satisfied.");
+            };
+        }
 
         Ok(())
     }
@@ -157,138 +115,263 @@ mod tests {
     use crate::ledger::{self, Ledger};
     use bitcoin::opcodes::all::*;
     use bitcoin::script::Builder;
-    use bitcoin::{Amount, OutPoint, Sequence};
+    use bitcoin::{absolute, transaction, Amount, ScriptBuf, Sequence, TxIn, Witness};
+    use rusqlite::params;
 
     #[test]
-    fn check_for_csv_with_block_height() {
-        let ledger = Ledger::new("check_for_csv_with_block_height");
+    fn sequence_locks_with_block_height() {
+        let ledger = Ledger::new("memory://sequence_locks_with_block_height").unwrap();
         let credential = ledger::Ledger::generate_credential_from_witness();
-        let xonly_pk = credential.x_only_public_key;
+        let address = credential.address.clone();
 
-        let txout = ledger.create_txout(Amount::from_sat(0x45), credential.address.script_pubkey());
+        let txout = ledger.create_txout(Amount::from_sat(0x45), address.script_pubkey());
         let tx = ledger.create_transaction(vec![], vec![txout]);
-        let utxo = OutPoint {
-            txid: tx.compute_txid(),
-            vout: 0,
-        };
-
-        let txid = ledger.add_transaction_unconditionally(tx.clone()).unwrap();
-        assert_eq!(ledger.get_transaction_block_height(&txid).unwrap(), 1);
-
-        ledger.mine_block(&credential.address).unwrap();
-        ledger.mine_block(&credential.address).unwrap();
-        ledger.mine_block(&credential.address).unwrap();
-        assert_eq!(ledger.get_block_height().unwrap(), 3);
-
-        let script = Builder::new()
-            .push_int(0x1_i64)
-            .push_opcode(OP_CSV)
-            .push_opcode(OP_DROP)
-            .push_x_only_key(&xonly_pk)
-            .push_opcode(OP_CHECKSIG)
-            .into_script();
-        ledger.check_sequence(utxo, script, 2).unwrap();
+        let txid = ledger.add_transaction_unconditionally(tx).unwrap();
+        ledger.mine_block(&address).unwrap();
+
+        // Output is in block 1, so it can be spent in block 4.
+        let tx = ledger.create_transaction(
+            vec![TxIn {
+                sequence: Sequence::from_height(3),
+                witness: credential.witness.clone().unwrap(),
+                ..ledger.create_txin(txid, 0)
+            }],
+            vec![ledger.create_txout(Amount::from_sat(0x44), address.script_pubkey())],
+        );
+        assert!(ledger.check_sequence_locks(&tx).is_err());
+        ledger.mine_block(&address).unwrap();
+        assert!(ledger.add_transaction(tx.clone()).is_err());
+
+        // Version 1 transactions and inputs with the disable flag don't have
+        // relative lock times.
+        let mut version_one = tx.clone();
+        version_one.version = transaction::Version::ONE;
+        ledger.check_sequence_locks(&version_one).unwrap();
+        let mut disabled = tx.clone();
+        disabled.input[0].sequence = Sequence(Sequence::from_height(3).0 | (1 << 31));
+        ledger.check_sequence_locks(&disabled).unwrap();
+
+        ledger.mine_block(&address).unwrap();
+        ledger.add_transaction(tx.clone()).unwrap();
+
+        // An output of a mempool transaction can only be spent without a lock.
+        let child_txid = tx.compute_txid();
+        let child = ledger.create_transaction(
+            vec![TxIn {
+                sequence: Sequence::from_height(1),
+                witness: credential.witness.clone().unwrap(),
+                ..ledger.create_txin(child_txid, 0)
+            }],
+            vec![ledger.create_txout(Amount::from_sat(0x44), address.script_pubkey())],
+        );
+        assert!(ledger.check_sequence_locks(&child).is_err());
+        let child = ledger.create_transaction(
+            vec![TxIn {
+                sequence: Sequence::ZERO,
+                witness: credential.witness.clone().unwrap(),
+                ..ledger.create_txin(child_txid, 0)
+            }],
+            vec![ledger.create_txout(Amount::from_sat(0x44), address.script_pubkey())],
+        );
+        ledger.check_sequence_locks(&child).unwrap();
+    }
 
-        for _ in 0..3 {
-            ledger.mine_block(&credential.address).unwrap();
+    #[test]
+    fn sequence_locks_with_time() {
+        let ledger = Ledger::new("memory://sequence_locks_with_time?time=1700000000").unwrap();
+        let credential = ledger::Ledger::generate_credential_from_witness();
+        let address = credential.address.clone();
+        for _ in 0..11 {
+            ledger.mine_block(&address).unwrap();
         }
-        assert_eq!(ledger.get_block_height().unwrap(), 6);
 
-        let script = Builder::new()
-            .push_int(0x1_i64)
-            .push_opcode(OP_CSV)
-            .push_opcode(OP_DROP)
-            .push_x_only_key(&xonly_pk)
-            .push_opcode(OP_CHECKSIG)
-            .into_script();
-        ledger.check_sequence(utxo, script, 2).unwrap();
-
-        for _ in 0..3 {
-            ledger.mine_block(&credential.address).unwrap();
-        }
-        assert_eq!(ledger.get_block_height().unwrap(), 9);
-        let script = Builder::new()
-            .push_int(0x45_i64)
-            .push_opcode(OP_CSV)
-            .push_opcode(OP_DROP)
-            .push_x_only_key(&xonly_pk)
-            .push_opcode(OP_CHECKSIG)
-            .into_script();
-        assert!(ledger.check_sequence(utxo, script, 0x46).is_err());
+        let txout = ledger.create_txout(Amount::from_sat(0x45), address.script_pubkey());
+        let tx = ledger.create_transaction(vec![], vec![txout]);
+        let txid = ledger.add_transaction_unconditionally(tx).unwrap();
+        ledger.mine_block(&address).unwrap();
+
+        // Median time past increases 600 seconds with every block, so 1024
+        // seconds pass after 2 blocks.
+        let tx = ledger.create_transaction(
+            vec![TxIn {
+                sequence: Sequence::from_512_second_intervals(2),
+                witness: credential.witness.clone().unwrap(),
+                ..ledger.create_txin(txid, 0)
+            }],
+            vec![ledger.create_txout(Amount::from_sat(0x44), address.script_pubkey())],
+        );
+        assert!(ledger.add_transaction(tx.clone()).is_err());
 
-        for _ in 0..0x100 {
-            ledger.mine_block(&credential.address).unwrap();
-        }
-        assert_eq!(ledger.get_block_height().unwrap(), 9 + 0x100);
-        let script = Builder::new()
-            .push_int(0x100_i64)
-            .push_opcode(OP_CSV)
-            .push_opcode(OP_DROP)
-            .push_x_only_key(&xonly_pk)
-            .push_opcode(OP_CHECKSIG)
-            .into_script();
-        ledger.check_sequence(utxo, script, 0x101).unwrap();
+        ledger.mine_block(&address).unwrap();
+        ledger.add_transaction(tx).unwrap();
     }
 
     #[test]
-    #[ignore = "Needs more work with creating inputs"]
-    fn check_csv_with_time_lock() {
-        let ledger = Ledger::new("check_csv_with_time_lock");
+    fn sequence_locks_at_height_zero() {
+        let ledger = Ledger::new("memory://sequence_locks_at_height_zero").unwrap();
         let credential = ledger::Ledger::generate_credential_from_witness();
-        let xonly_pk = credential.x_only_public_key;
+        let address = credential.address.clone();
 
-        let txout = ledger.create_txout(Amount::from_sat(0x45), credential.address.script_pubkey());
+        let txout = ledger.create_txout(Amount::from_sat(0x45), address.script_pubkey());
         let tx = ledger.create_transaction(vec![], vec![txout]);
-        let utxo = OutPoint {
-            txid: tx.compute_txid(),
-            vout: 0,
-        };
+        let txid = ledger.add_transaction_unconditionally(tx).unwrap();
+        ledger
+            .database
+            .lock()
+            .unwrap()
+            .execute(
+                "UPDATE transactions SET block_height = 0 WHERE txid = ?1",
+                params![txid.to_string()],
+            )
+            .unwrap();
+
+        // There is no block before the output's block, so time based lock
+        // can't be checked.
+        let tx = ledger.create_transaction(
+            vec![TxIn {
+                sequence: Sequence::from_512_second_intervals(1),
+                witness: credential.witness.clone().unwrap(),
+                ..ledger.create_txin(txid, 0)
+            }],
+            vec![ledger.create_txout(Amount::from_sat(0x44), address.script_pubkey())],
+        );
+        assert!(ledger.check_sequence_locks(&tx).is_err());
+    }
 
-        ledger.add_transaction_unconditionally(tx.clone()).unwrap();
-        ledger.mine_block(&credential.address).unwrap();
+    #[test]
+    fn check_sequence_verify_anywhere() {
+        let ledger = Ledger::new("memory://check_sequence_verify_anywhere").unwrap();
+        let address = ledger::Ledger::generate_credential_from_witness().address;
 
-        let sequence = Sequence::from_512_second_intervals(2);
+        // OP_CSV is only executed if the first witness element is true.
         let script = Builder::new()
-            .push_sequence(sequence)
+            .push_opcode(OP_IF)
+            .push_int(5)
             .push_opcode(OP_CSV)
             .push_opcode(OP_DROP)
-            .push_x_only_key(&xonly_pk)
-            .push_opcode(OP_CHECKSIG)
+            .push_opcode(OP_ENDIF)
+            .push_opcode(OP_PUSHNUM_1)
             .into_script();
-        ledger
-            .check_sequence(utxo, script, sequence.to_consensus_u32())
-            .unwrap();
+        let script_pubkey = ScriptBuf::new_p2wsh(&script.wscript_hash());
 
-        ledger.mine_block(&credential.address).unwrap();
+        let mut txids = vec![];
+        for i in 0..2 {
+            let txout = ledger.create_txout(Amount::from_sat(0x45 + i), script_pubkey.clone());
+            let tx = ledger.create_transaction(vec![], vec![txout]);
+            txids.push(ledger.add_transaction_unconditionally(tx).unwrap());
+        }
+        for _ in 0..10 {
+            ledger.mine_block(&address).unwrap();
+        }
 
-        let sequence = Sequence::from_512_second_intervals(0x45);
+        let witness = |branch: Vec<u8>| Witness::from_slice(&[vec![], branch, script.to_bytes()]);
+
+        // Relative lock time of the input is satisfied, but it is lower than
+        // the script's.
+        let tx = ledger.create_transaction(
+            vec![TxIn {
+                sequence: Sequence::from_height(4),
+                witness: witness(vec![1]),
+                ..ledger.create_txin(txids[0], 0)
+            }],
+            vec![ledger.create_txout(Amount::from_sat(0x44), address.script_pubkey())],
+        );
+        ledger.check_sequence_locks(&tx).unwrap();
+        assert!(ledger.add_transaction(tx).is_err());
+
+        // OP_CSV in the other branch is not executed.
+        let tx = ledger.create_transaction(
+            vec![TxIn {
+                sequence: Sequence::MAX,
+                witness: witness(vec![]),
+                ..ledger.create_txin(txids[0], 0)
+            }],
+            vec![ledger.create_txout(Amount::from_sat(0x44), address.script_pubkey())],
+        );
+        ledger.add_transaction(tx).unwrap();
+
+        // OP_CSV fails if the relative lock time is disabled.
+        let mut tx = ledger.create_transaction(
+            vec![TxIn {
+                sequence: Sequence::MAX,
+                witness: witness(vec![1]),
+                ..ledger.create_txin(txids[1], 0)
+            }],
+            vec![ledger.create_txout(Amount::from_sat(0x44), address.script_pubkey())],
+        );
+        assert!(ledger.add_transaction(tx.clone()).is_err());
+        // And for version 1 transactions.
+        tx.version = transaction::Version::ONE;
+        tx.input[0].sequence = Sequence::from_height(5);
+        assert!(ledger.add_transaction(tx).is_err());
+
+        let tx = ledger.create_transaction(
+            vec![TxIn {
+                sequence: Sequence::from_height(5),
+                witness: witness(vec![1]),
+                ..ledger.create_txin(txids[1], 0)
+            }],
+            vec![ledger.create_txout(Amount::from_sat(0x44), address.script_pubkey())],
+        );
+        ledger.add_transaction(tx).unwrap();
+    }
+
+    #[test]
+    fn check_lock_time_verify() {
+        let ledger = Ledger::new("memory://check_lock_time_verify").unwrap();
+        let address = ledger::Ledger::generate_credential_from_witness().address;
+
+        // Spendable after block 10.
         let script = Builder::new()
-            .push_sequence(sequence)
-            .push_opcode(OP_CSV)
+            .push_int(10)
+            .push_opcode(OP_CLTV)
             .push_opcode(OP_DROP)
-            .push_x_only_key(&xonly_pk)
-            .push_opcode(OP_CHECKSIG)
+            .push_opcode(OP_PUSHNUM_1)
             .into_script();
+        let txout = ledger.create_txout(
+            Amount::from_sat(0x45),
+            ScriptBuf::new_p2wsh(&script.wscript_hash()),
+        );
+        let tx = ledger.create_transaction(vec![], vec![txout]);
+        let txid = ledger.add_transaction_unconditionally(tx).unwrap();
+        ledger.mine_block(&address).unwrap();
+
+        let spend = |lock_time: u32, sequence: Sequence| {
+            let txin = TxIn {
+                sequence,
+                witness: Witness::from_slice(&[vec![], script.to_bytes()]),
+                ..ledger.create_txin(txid, 0)
+            };
+            let txout = ledger.create_txout(Amount::from_sat(0x44), address.script_pubkey());
+            let mut tx = ledger.create_transaction(vec![txin], vec![txout]);
+            tx.lock_time = absolute::LockTime::from_consensus(lock_time);
+
+            tx
+        };
+
+        // Transaction is final but its lock time is lower than the script's.
         assert!(ledger
-            .check_sequence(
-                utxo,
-                script,
-                Sequence::from_512_second_intervals(0x44).to_consensus_u32(),
-            )
+            .add_transaction(spend(1, Sequence::ENABLE_LOCKTIME_NO_RBF))
+            .is_err());
+        // Transaction is not final until block 11.
+        assert!(ledger
+            .add_transaction(spend(10, Sequence::ENABLE_LOCKTIME_NO_RBF))
             .is_err());
 
-        ledger.mine_block(&credential.address).unwrap();
+        for _ in 0..9 {
+            ledger.mine_block(&address).unwrap();
+        }
+        assert_eq!(ledger.get_block_height().unwrap(), 10);
+
+        // Lock time can't be disabled with the final sequence.
+        assert!(ledger.add_transaction(spend(10, Sequence::MAX)).is_err());
+        // Time based lock time doesn't satisfy a height based one.
+        assert!(ledger
+            .add_transaction(spend(500_000_000, Sequence::ENABLE_LOCKTIME_NO_RBF))
+            .is_err());
 
-        let sequence = Sequence::from_512_second_intervals(300);
-        let script = Builder::new()
-            .push_sequence(sequence)
-            .push_opcode(OP_CSV)
-            .push_opcode(OP_DROP)
-            .push_x_only_key(&xonly_pk)
-            .push_opcode(OP_CHECKSIG)
-            .into_script();
         ledger
-            .check_sequence(utxo, script, sequence.to_consensus_u32())
+            .add_transaction(spend(10, Sequence::ENABLE_LOCKTIME_NO_RBF))
             .unwrap();
     }
 }
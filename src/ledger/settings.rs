@@ -0,0 +1,278 @@
+//! # Ledger Settings
+//!
+//! Settings are kept in the ledger's database, so they are bound to a ledger
+//! rather than a process. They can be given as parameters of the ledger path,
+//! like `name?seed=42&time=1700000000`.
+//!
+//! A ledger with the `seed` and `time` settings is deterministic: All the
+//! randomness it uses is derived from the seed and the first block's time is
+//! the given UNIX timestamp. Therefore, same operations result in the same
+//! block hashes and TXIDs in every run.
+//!
+//! `network` setting selects the network that addresses are encoded for and
+//! validated against. It can be one of `regtest` (default), `testnet`,
+//! `signet` or `mainnet`.
+//!
+//! `coinbase_maturity` setting is the number of confirmations a coinbase
+//! output needs before it can be spent. Default is 100, like the real
+//! networks. Setting it to 0 disables the check.
+//!
+//! `mock_time` setting is the time of the ledger's clock. It can't be given as
+//! a path parameter and it is set with `Ledger::set_mock_time`.
+
+use super::{errors::LedgerError, Ledger};
+use bitcoin::{
+    hashes::{sha256, Hash},
+    p2p::Magic,
+    Network,
+};
+use rusqlite::params;
+use secp256k1::rand::{self, RngCore};
+use std::str::FromStr;
+
+/// Seed for the ledger's randomness.
+pub const SEED: &str = "seed";
+/// UNIX timestamp of the first block.
+pub const TIME: &str = "time";
+/// Network of the ledger, saved as it's magic.
+pub const NETWORK: &str = "network";
+/// Confirmations needed to spend a coinbase output.
+pub const COINBASE_MATURITY: &str = "coinbase_maturity";
+/// Time of the ledger's clock, if it is mocked.
+pub const MOCK_TIME: &str = "mock_time";
+/// Ledger is migrated from a database with blocks on top of the placeholder
+/// genesis block, which point to an all zero previous block hash.
+pub const LEGACY_GENESIS: &str = "legacy_genesis";
+/// Number of random values derived from the seed so far.
+const RANDOM_COUNTER: &str = "random_counter";
+
+/// Settings that can be given as ledger path parameters.
+const PATH_SETTINGS: [&str; 4] = [SEED, TIME, NETWORK, COINBASE_MATURITY];
+
+/// Default value of the `coinbase_maturity` setting.
+const DEFAULT_COINBASE_MATURITY: u32 = 100;
+
+/// Setting names and values, parsed from a ledger path.
+type PathSettings<'a> = Vec<(&'a str, i64)>;
+
+impl Ledger {
+    /// Splits `path` into the ledger path and the settings in it's parameters.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a parameter is unknown or it's value is invalid.
+    pub fn parse_path_settings(path: &str) -> Result<(&str, PathSettings<'_>), LedgerError> {
+        let Some((path, parameters)) = path.split_once('?') else {
+            return Ok((path, Vec::new()));
+        };
+
+        let mut settings = Vec::new();
+        for parameter in parameters.split('&').filter(|p| !p.is_empty()) {
+            let (name, value) = parameter.split_once('=').unwrap_or((parameter, ""));
+
+            if !PATH_SETTINGS.contains(&name) {
+                return Err(LedgerError::Database(format!(
+                    "Unknown ledger parameter {name}; Available ones are {PATH_SETTINGS:?}"
+                )));
+            }
+
+            if name == NETWORK {
+                let network = match value {
+                    "mainnet" => Ok(Network::Bitcoin),
+                    _ => Network::from_str(value).or_else(|_| Network::from_core_arg(value)),
+                };
+
+                match network {
+                    Ok(network) => settings.push((name, Ledger::encode_network(network))),
+                    Err(e) => {
+                        return Err(LedgerError::Database(format!(
+                            "Ledger parameter {name} must be a network name, not {value:?}: {e}"
+                        )))
+                    }
+                }
+
+                continue;
+            }
+
+            let value = match value.parse::<u64>() {
+                Ok(value) => value,
+                Err(e) => {
+                    return Err(LedgerError::Database(format!(
+                        "Ledger parameter {name} must be an unsigned integer, not {value:?}: {e}"
+                    )))
+                }
+            };
+
+            // Values must fit in the type they are read as.
+            let max = match name {
+                TIME | COINBASE_MATURITY => u32::MAX as i64,
+                _ => i64::MAX,
+            };
+            match i64::try_from(value) {
+                Ok(value) if value <= max => settings.push((name, value)),
+                _ => {
+                    return Err(LedgerError::Database(format!(
+                        "Ledger parameter {name} must be at most {max}, not {value}"
+                    )))
+                }
+            }
+        }
+
+        Ok((path, settings))
+    }
+
+    /// Returns the value of the setting `name`, if it is set.
+    pub fn get_setting(&self, name: &str) -> Result<Option<i64>, LedgerError> {
+        match self.database.lock().unwrap().query_row(
+            "SELECT value FROM settings WHERE name = ?1",
+            params![name],
+            |row| row.get::<_, i64>(0),
+        ) {
+            Ok(value) => Ok(Some(value)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(LedgerError::Database(format!(
+                "Couldn't read setting {}: {}",
+                name, e
+            ))),
+        }
+    }
+
+    pub fn set_setting(&self, name: &str, value: i64) -> Result<(), LedgerError> {
+        if let Err(e) = self.database.lock().unwrap().execute(
+            "INSERT INTO settings (name, value) VALUES (?1, ?2) ON CONFLICT (name) DO UPDATE SET value = ?2",
+            params![name, value],
+        ) {
+            return Err(LedgerError::Database(format!(
+                "Couldn't set setting {} to {}: {}",
+                name, value, e
+            )));
+        };
+        tracing::trace!("Setting {name} is set to {value}");
+
+        Ok(())
+    }
+
+    /// Returns the network of the ledger. Default is regtest.
+    pub fn get_network(&self) -> Result<Network, LedgerError> {
+        let Some(magic) = self.get_setting(NETWORK)? else {
+            return Ok(Network::Regtest);
+        };
+
+        match Ledger::decode_network(magic) {
+            Some(network) => Ok(network),
+            None => Err(LedgerError::Database(format!(
+                "Unknown network magic {magic:#x} in settings"
+            ))),
+        }
+    }
+
+    /// Returns the number of confirmations a coinbase output needs before it
+    /// can be spent. Default is 100.
+    pub fn get_coinbase_maturity(&self) -> Result<u32, LedgerError> {
+        Ok(self
+            .get_setting(COINBASE_MATURITY)?
+            .map(|maturity| maturity as u32)
+            .unwrap_or(DEFAULT_COINBASE_MATURITY))
+    }
+
+    fn encode_network(network: Network) -> i64 {
+        u32::from_le_bytes(network.magic().to_bytes()) as i64
+    }
+
+    /// Decodes a network setting value, which is the network's magic.
+    pub(super) fn decode_network(value: i64) -> Option<Network> {
+        Network::from_magic(Magic::from_bytes((value as u32).to_le_bytes()))
+    }
+
+    /// Returns 32 random bytes. If ledger has a seed, bytes are derived from
+    /// it and a counter that is increased on every call. Otherwise, they are
+    /// truly random.
+    pub fn get_random_bytes(&self) -> Result<[u8; 32], LedgerError> {
+        let Some(seed) = self.get_setting(SEED)? else {
+            let mut bytes = [0u8; 32];
+            rand::thread_rng().fill_bytes(&mut bytes);
+
+            return Ok(bytes);
+        };
+
+        let counter = self.get_setting(RANDOM_COUNTER)?.unwrap_or(0);
+        self.set_setting(RANDOM_COUNTER, counter + 1)?;
+
+        let preimage = [seed.to_le_bytes(), counter.to_le_bytes()].concat();
+
+        Ok(sha256::Hash::hash(&preimage).to_byte_array())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_path_settings() {
+        assert_eq!(
+            Ledger::parse_path_settings("name").unwrap(),
+            ("name", vec![])
+        );
+        assert_eq!(
+            Ledger::parse_path_settings("memory://name?seed=42&time=1700000000").unwrap(),
+            ("memory://name", vec![(SEED, 42), (TIME, 1700000000)])
+        );
+
+        assert_eq!(
+            Ledger::parse_path_settings("name?seed=9223372036854775807&time=4294967295").unwrap(),
+            ("name", vec![(SEED, i64::MAX), (TIME, u32::MAX as i64)])
+        );
+
+        assert!(Ledger::parse_path_settings("name?seed=-1").is_err());
+        assert!(Ledger::parse_path_settings("name?seed=18446744073709551615").is_err());
+        assert!(Ledger::parse_path_settings("name?time=5000000000").is_err());
+        assert!(Ledger::parse_path_settings("name?coinbase_maturity=4294967396").is_err());
+        assert!(Ledger::parse_path_settings("name?seed").is_err());
+        assert!(Ledger::parse_path_settings("name?foo=1").is_err());
+        assert!(Ledger::parse_path_settings("name?network=foo").is_err());
+    }
+
+    #[test]
+    fn network() {
+        let ledger = Ledger::new("memory://settings_network").unwrap();
+        assert_eq!(ledger.get_network().unwrap(), Network::Regtest);
+
+        for (name, network) in [
+            ("regtest", Network::Regtest),
+            ("testnet", Network::Testnet),
+            ("signet", Network::Signet),
+            ("mainnet", Network::Bitcoin),
+            ("main", Network::Bitcoin),
+        ] {
+            let ledger = Ledger::new(&format!("memory://settings_network?network={name}")).unwrap();
+            assert_eq!(ledger.get_network().unwrap(), network);
+        }
+    }
+
+    #[test]
+    fn coinbase_maturity() {
+        let ledger = Ledger::new("memory://settings_coinbase_maturity").unwrap();
+        assert_eq!(ledger.get_coinbase_maturity().unwrap(), 100);
+
+        let ledger =
+            Ledger::new("memory://settings_coinbase_maturity?coinbase_maturity=0").unwrap();
+        assert_eq!(ledger.get_coinbase_maturity().unwrap(), 0);
+    }
+
+    #[test]
+    fn deterministic_randomness() {
+        let ledger = Ledger::new("memory://deterministic_randomness?seed=42").unwrap();
+        let other = Ledger::new("memory://deterministic_randomness_other?seed=42").unwrap();
+
+        let bytes = ledger.get_random_bytes().unwrap();
+        assert_ne!(bytes, ledger.get_random_bytes().unwrap());
+        assert_eq!(bytes, other.get_random_bytes().unwrap());
+
+        let random = Ledger::new("memory://deterministic_randomness_random").unwrap();
+        assert_ne!(
+            random.get_random_bytes().unwrap(),
+            random.get_random_bytes().unwrap()
+        );
+    }
+}
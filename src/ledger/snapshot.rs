@@ -0,0 +1,166 @@
+//! # Snapshot Related Ledger Operations
+//!
+//! A snapshot is a copy of the ledger tables, saved in the same database with
+//! `snapshot_<id>_` prefix. Ledger can be restored to a snapshot as many times
+//! as needed.
+
+use super::{errors::LedgerError, Ledger};
+use rusqlite::{params, Connection};
+
+/// Tables that hold the ledger state.
+const LEDGER_TABLES: [&str; 8] = [
+    "blocks",
+    "mempool",
+    "transactions",
+    "utxos",
+    "undo",
+    "stale_blocks",
+    "history",
+    "settings",
+];
+
+impl Ledger {
+    /// Saves current state of the ledger and returns an ID for it, which can
+    /// be used with [`Ledger::restore`].
+    #[tracing::instrument(skip(self))]
+    pub fn snapshot(&self) -> Result<u32, LedgerError> {
+        self.atomically(|| {
+            let database = self.database.lock().unwrap();
+
+            let id = match database.execute("INSERT INTO snapshots DEFAULT VALUES", params![]) {
+                Ok(_) => database.last_insert_rowid() as u32,
+                Err(e) => {
+                    return Err(LedgerError::Snapshot(format!(
+                        "Couldn't create a new snapshot: {}",
+                        e
+                    )))
+                }
+            };
+
+            for table in LEDGER_TABLES {
+                if let Err(e) = database.execute_batch(&format!(
+                    "CREATE TABLE snapshot_{id}_{table} AS SELECT * FROM {table} ORDER BY rowid"
+                )) {
+                    return Err(LedgerError::Snapshot(format!(
+                        "Couldn't copy table {} to snapshot {}: {}",
+                        table, id, e
+                    )));
+                }
+            }
+            tracing::debug!("Snapshot {id} is created");
+
+            Ok(id)
+        })
+    }
+
+    /// Restores the ledger to the state it was in when the snapshot with `id`
+    /// was taken. Snapshot is kept, so it can be restored again later.
+    #[tracing::instrument(skip(self))]
+    pub fn restore(&self, id: u32) -> Result<(), LedgerError> {
+        self.atomically(|| {
+            let database = self.database.lock().unwrap();
+
+            if let Err(e) = database.query_row(
+                "SELECT id FROM snapshots WHERE id = ?1",
+                params![id],
+                |_| Ok(()),
+            ) {
+                return Err(LedgerError::Snapshot(format!(
+                    "Couldn't find snapshot {}: {}",
+                    id, e
+                )));
+            }
+
+            for table in LEDGER_TABLES {
+                if let Err(e) = database.execute_batch(&format!(
+                    "DELETE FROM {table};
+                    INSERT INTO {table} SELECT * FROM snapshot_{id}_{table} ORDER BY rowid;"
+                )) {
+                    return Err(LedgerError::Snapshot(format!(
+                        "Couldn't restore table {} from snapshot {}: {}",
+                        table, id, e
+                    )));
+                }
+            }
+            tracing::debug!("Ledger is restored to snapshot {id}");
+
+            Ok(())
+        })
+    }
+
+    /// Returns the names of the tables that hold snapshot data.
+    pub fn get_snapshot_tables(database: &Connection) -> Result<Vec<String>, rusqlite::Error> {
+        let mut stmt = database.prepare(
+            "SELECT name FROM sqlite_master WHERE type = 'table' AND name LIKE 'snapshot\\_%' ESCAPE '\\'",
+        )?;
+        let tables = stmt.query_map([], |row| row.get(0))?;
+
+        tables.collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ledger::{self, Ledger};
+    use bitcoin::{Amount, OutPoint};
+
+    #[test]
+    fn snapshot_and_restore() {
+        let ledger = Ledger::new("snapshot_and_restore").unwrap();
+        let address = ledger::Ledger::generate_credential_from_witness().address;
+
+        for _ in 0..10 {
+            ledger.mine_block(&address).unwrap();
+        }
+        let tx = ledger.create_transaction(
+            vec![],
+            vec![ledger.create_txout(Amount::from_sat(0x45), address.script_pubkey())],
+        );
+        let txid = ledger.add_transaction_unconditionally(tx).unwrap();
+        let utxo = OutPoint { txid, vout: 0 };
+
+        let id = ledger.snapshot().unwrap();
+
+        for _ in 0..2 {
+            ledger.mine_block(&address).unwrap();
+            assert_eq!(ledger.get_block_height().unwrap(), 11);
+            assert!(ledger.get_mempool_transactions().is_empty());
+
+            let txin = ledger.create_txin(txid, 0);
+            let tx = ledger.create_transaction(vec![txin], vec![]);
+            ledger.add_transaction_unconditionally(tx).unwrap();
+            assert!(ledger.is_utxo_spent(utxo));
+
+            ledger.mine_block(&address).unwrap();
+
+            ledger.restore(id).unwrap();
+            assert_eq!(ledger.get_block_height().unwrap(), 10);
+            assert_eq!(ledger.get_mempool_transactions().len(), 1);
+            assert!(ledger.get_mempool_transaction(txid).is_some());
+            assert!(!ledger.is_utxo_spent(utxo));
+            assert_eq!(ledger._get_transactions().len(), 11);
+        }
+
+        assert!(ledger.restore(id + 1).is_err());
+    }
+
+    #[test]
+    fn restore_keeps_stale_blocks() {
+        let ledger = Ledger::new("restore_keeps_stale_blocks").unwrap();
+        let address = ledger::Ledger::generate_credential_from_witness().address;
+
+        let block_hash = ledger.mine_block(&address).unwrap();
+        ledger.invalidate_block(block_hash).unwrap();
+        let id = ledger.snapshot().unwrap();
+
+        ledger.reconsider_block(block_hash).unwrap();
+        assert_eq!(ledger.get_block_height().unwrap(), 1);
+
+        ledger.restore(id).unwrap();
+        assert_eq!(ledger.get_block_height().unwrap(), 0);
+        assert_eq!(ledger.get_chain_tips().unwrap().len(), 2);
+
+        ledger.reconsider_block(block_hash).unwrap();
+        assert_eq!(ledger.get_block_height().unwrap(), 1);
+    }
+}
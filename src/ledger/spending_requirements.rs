@@ -260,7 +260,7 @@ mod test {
 
     #[test]
     fn p2wpkh_check() {
-        let ledger = Ledger::new("p2wpkh_check");
+        let ledger = Ledger::new("p2wpkh_check").unwrap();
         let credential = Ledger::generate_credential_from_witness();
 
         let wpkh = bitcoin::PublicKey::new(credential.public_key)
@@ -317,7 +317,7 @@ mod test {
 
     #[test]
     fn p2wsh_check() {
-        let ledger = Ledger::new("p2wsh_check");
+        let ledger = Ledger::new("p2wsh_check").unwrap();
         let script = Builder::new()
             .push_int(1234)
             .push_opcode(OP_EQUAL)
@@ -362,7 +362,7 @@ mod test {
 
     #[test]
     fn p2tr_check() {
-        let ledger = Ledger::new("p2tr_check");
+        let ledger = Ledger::new("p2tr_check").unwrap();
         let secp = bitcoin::secp256k1::Secp256k1::new();
         let internal_key = UntweakedPublicKey::from(
             bitcoin::secp256k1::PublicKey::from_str(
@@ -427,7 +427,7 @@ mod test {
 
     #[test]
     fn p2tr_random_witness() {
-        let ledger = Ledger::new("p2tr_random_witness");
+        let ledger = Ledger::new("p2tr_random_witness").unwrap();
         let credential = Ledger::generate_credential_from_witness();
 
         let output = TxOut {
@@ -1,6 +1,8 @@
 //! # Transaction Related Ledger Operations
 
-use super::{errors::LedgerError, spending_requirements::SpendingRequirementsReturn, Ledger};
+use super::{
+    errors::LedgerError, spending_requirements::SpendingRequirementsReturn, utxo::Utxo, Ledger,
+};
 use crate::utils::{self, hex_to_array, Hash256};
 use bitcoin::{
     absolute::{self, LockTime},
@@ -9,13 +11,32 @@ use bitcoin::{
         Decodable, Encodable,
     },
     hashes::{sha256d, Hash},
-    opcodes::all::OP_RETURN,
+    opcodes::{all::OP_RETURN, OP_0},
+    script::{Builder, PushBytesBuf},
     Address, Amount, BlockHash, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxMerkleNode,
     TxOut, Txid, Witness, Wtxid,
 };
 use bitcoin_scriptexec::{ExecCtx, TxTemplate};
 use rs_merkle::Hasher;
 use rusqlite::params;
+use std::collections::HashSet;
+
+/// Maximum size of a coinbase transaction's scriptSig.
+const MAX_COINBASE_SCRIPT_SIG_SIZE: usize = 100;
+
+/// Customizations for a block's coinbase transaction. Default value creates
+/// a standard coinbase transaction, which pays the block reward to the miner's
+/// address.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CoinbaseOptions {
+    /// Data that is pushed to the scriptSig, after the block height.
+    pub script_sig_data: Vec<u8>,
+    /// Script that receives the block reward, instead of the miner's address.
+    pub payout_script: Option<ScriptBuf>,
+    /// Outputs that come after the block reward output. Their values are
+    /// deducted from the block reward.
+    pub extra_outputs: Vec<TxOut>,
+}
 
 impl Ledger {
     /// Adds transaction to blockchain, after verifying.
@@ -57,7 +78,10 @@ impl Ledger {
 
         self.add_mempool_transaction(txid)?;
 
-        self.handle_transaction_utxos(&transaction)?;
+        // Spent outputs are needed for the history, so this must be done
+        // before updating UTXOs.
+        self.add_transaction_history(&transaction)?;
+        self.handle_transaction_utxos(&transaction, current_block_height + 1)?;
 
         Ok(txid)
     }
@@ -157,13 +181,19 @@ impl Ledger {
 
     /// Checks if a transaction is valid or not. Steps:
     ///
-    /// 1. Is input value is larger than the output value?
-    /// 2. Is satisfies it's spending requirements?
-    /// 3. Is script execution successful?
-    ///
-    /// No checks for if that UTXO is spendable or not.
+    /// 1. Are all the inputs unspent?
+    /// 2. Is it final in the next block?
+    /// 3. Are relative lock times of the inputs satisfied?
+    /// 4. Are all the coinbase inputs mature?
+    /// 5. Is input value is larger than the output value?
+    /// 6. Is satisfies it's spending requirements?
+    /// 7. Is script execution successful?
     #[tracing::instrument(skip_all)]
     pub fn check_transaction(&self, transaction: &Transaction) -> Result<(), LedgerError> {
+        self.check_transaction_inputs(transaction)?;
+        self.check_transaction_finality(transaction)?;
+        self.check_sequence_locks(transaction)?;
+        self.check_coinbase_maturity(transaction)?;
         self.check_transaction_funds(transaction)?;
 
         let mut txouts = vec![];
@@ -175,14 +205,7 @@ impl Ledger {
                 return Err(LedgerError::Transaction(msg.to_string()));
             }
 
-            let tx = self.get_transaction(input.previous_output.txid)?;
-            let txout = tx
-                .output
-                .get(input.previous_output.vout as usize)
-                .unwrap()
-                .to_owned();
-
-            txouts.push(txout);
+            txouts.push(self.get_utxo(input.previous_output)?.txout);
         }
         tracing::trace!("UTXOs that will be spent in this transaction: {txouts:?}");
 
@@ -220,6 +243,112 @@ impl Ledger {
         Ok(())
     }
 
+    /// Checks if every input of the transaction spends an existing UTXO and
+    /// no UTXO is spent twice in the same transaction. Because mempool
+    /// transactions also remove their inputs from the UTXO set, this rejects
+    /// conflicts with both mempool and mined transactions.
+    pub fn check_transaction_inputs(&self, transaction: &Transaction) -> Result<(), LedgerError> {
+        let mut outpoints = HashSet::new();
+
+        for input in &transaction.input {
+            if !outpoints.insert(input.previous_output) {
+                return Err(LedgerError::Transaction(format!(
+                    "Input {} is spent more than once in the same transaction",
+                    input.previous_output
+                )));
+            }
+
+            if self.is_utxo_spent(input.previous_output) {
+                tracing::error!("Input {} is either spent or unknown", input.previous_output);
+
+                return Err(LedgerError::InputMissingOrSpent(input.previous_output));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks if the transaction is final in the next block, as in BIP113:
+    /// Height based lock times are compared against the next block's height
+    /// and time based ones against the median time past of the tip.
+    pub fn check_transaction_finality(&self, transaction: &Transaction) -> Result<(), LedgerError> {
+        let tip_height = self.get_block_height()?;
+        let median_time_past = self.get_median_time_past(self.get_block_hash(tip_height)?)?;
+
+        if !Ledger::is_transaction_final(transaction, tip_height + 1, median_time_past) {
+            tracing::error!(
+                "Transaction {} is locked until {}",
+                transaction.compute_txid(),
+                transaction.lock_time
+            );
+
+            return Err(LedgerError::Transaction(format!(
+                "Transaction {} is not final: It is locked until {}, but next block's height is {} and median time past is {}",
+                transaction.compute_txid(),
+                transaction.lock_time,
+                tip_height + 1,
+                median_time_past
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Returns true if the transaction can be included in a block at `height`,
+    /// which comes after a block with `median_time_past`. Lock time is ignored
+    /// if every input has the final sequence.
+    pub fn is_transaction_final(
+        transaction: &Transaction,
+        height: u32,
+        median_time_past: u32,
+    ) -> bool {
+        let lock_time = transaction.lock_time.to_consensus_u32();
+        if lock_time == 0 {
+            return true;
+        }
+
+        let limit = match transaction.lock_time {
+            LockTime::Blocks(_) => height,
+            LockTime::Seconds(_) => median_time_past,
+        };
+        if lock_time < limit {
+            return true;
+        }
+
+        transaction
+            .input
+            .iter()
+            .all(|input| input.sequence == Sequence::MAX)
+    }
+
+    /// Checks if every coinbase output that the transaction spends has enough
+    /// confirmations, when the transaction is included in the next block.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`LedgerError::ImmatureCoinbase`] for the first immature input.
+    pub fn check_coinbase_maturity(&self, transaction: &Transaction) -> Result<(), LedgerError> {
+        let maturity = self.get_coinbase_maturity()?;
+        let spend_height = self.get_block_height()? + 1;
+
+        for input in &transaction.input {
+            let utxo = self.get_utxo(input.previous_output)?;
+            let confirmations = spend_height.saturating_sub(utxo.height);
+
+            if utxo.coinbase && confirmations < maturity {
+                tracing::error!(
+                    "Input {} spends a coinbase output with {} confirmations",
+                    input.previous_output,
+                    confirmations
+                );
+
+                return Err(LedgerError::ImmatureCoinbase(input.previous_output));
+            }
+        }
+
+        Ok(())
+    }
+
     /// Checks if transactions input amount is equal or bigger than the output
     /// amount.
     pub fn check_transaction_funds(&self, transaction: &Transaction) -> Result<(), LedgerError> {
@@ -235,11 +364,11 @@ impl Ledger {
         }
     }
 
-    /// Calculates a transaction's total output value.
+    /// Calculates a transaction's total input value.
     ///
-    /// # Panics
+    /// # Errors
     ///
-    /// Panics if found UTXO doesn't match transaction.
+    /// Returns an error if an input is either spent or doesn't exist.
     pub fn calculate_transaction_input_value(
         &self,
         transaction: &Transaction,
@@ -247,12 +376,7 @@ impl Ledger {
         let mut amount = Amount::from_sat(0);
 
         for input in &transaction.input {
-            amount += self
-                .get_transaction(input.previous_output.txid)?
-                .output
-                .get(input.previous_output.vout as usize)
-                .unwrap()
-                .value;
+            amount += self.get_utxo(input.previous_output)?.txout.value;
         }
 
         tracing::trace!("Transaction's input value in total is {amount}");
@@ -269,15 +393,42 @@ impl Ledger {
         amount
     }
 
-    /// Removes inputs from UTXOs and adds outputs to UTXOs.
-    pub fn handle_transaction_utxos(&self, transaction: &Transaction) -> Result<(), LedgerError> {
+    /// Calculates fee of a transaction that is in the ledger, using the
+    /// transactions that created it's inputs. Transactions that spend unknown
+    /// outputs, like the ones created by `send_to_address`, don't pay any fees.
+    pub fn calculate_transaction_fee(&self, transaction: &Transaction) -> Amount {
+        if transaction.is_coinbase() {
+            return Amount::ZERO;
+        }
+
+        let mut input_value = Amount::ZERO;
+        for input in &transaction.input {
+            match self.get_utxo_from_transaction(input.previous_output) {
+                Ok(utxo) => input_value += utxo.txout.value,
+                Err(_) => return Amount::ZERO,
+            }
+        }
+
+        input_value
+            .checked_sub(self.calculate_transaction_output_value(transaction))
+            .unwrap_or(Amount::ZERO)
+    }
+
+    /// Removes inputs from UTXOs and adds outputs to UTXOs. `height` is the
+    /// height of the block that includes the transaction.
+    pub fn handle_transaction_utxos(
+        &self,
+        transaction: &Transaction,
+        height: u32,
+    ) -> Result<(), LedgerError> {
         for input in &transaction.input {
             self.remove_utxo(input.previous_output)?;
         }
 
-        let txid = transaction.compute_txid();
         for vout in 0..(transaction.output.len() as u32) {
-            self.add_utxo(OutPoint { txid, vout })?;
+            // Output surely exists, because `vout` is in range.
+            let utxo = Utxo::from_transaction(transaction, vout, height).unwrap();
+            self.add_utxo(&utxo)?;
         }
 
         Ok(())
@@ -315,24 +466,104 @@ impl Ledger {
     ///
     /// - address: Miner's address
     /// - wtxid_merkle_root: Merkle root of all the transaction wTXID's
+    /// - block_height: Height of the block that will include this transaction
+    /// - fees: Total fees of the transactions in the block, which are paid to
+    ///   miner alongside with the block subsidy
+    /// - options: Customizations for the scriptSig and outputs
+    ///
+    /// ScriptSig starts with the block height, as BIP34 requires.
     pub fn create_coinbase_transaction(
         &self,
         address: &Address,
         wtxids: Vec<Wtxid>,
+        block_height: u32,
+        fees: Amount,
+        options: &CoinbaseOptions,
     ) -> Result<Transaction, LedgerError> {
         tracing::trace!("Creating coinbase transaction for address {address:?}");
 
-        let current_block_height = self.get_block_height()? + 1;
-        let mut script_sig = ScriptBuf::new();
-        script_sig.push_slice(current_block_height.to_be_bytes());
+        let mut script_sig = Builder::new().push_int(block_height as i64);
+        if !options.script_sig_data.is_empty() {
+            let data = match PushBytesBuf::try_from(options.script_sig_data.clone()) {
+                Ok(data) => data,
+                Err(e) => {
+                    return Err(LedgerError::Transaction(format!(
+                        "Coinbase scriptSig data can't be pushed: {}",
+                        e
+                    )))
+                }
+            };
+            script_sig = script_sig.push_slice(data);
+        }
+        // ScriptSig must be at least 2 bytes long, which is not the case for
+        // the heights that are pushed with a single opcode.
+        if script_sig.len() < 2 {
+            script_sig = script_sig.push_opcode(OP_0);
+        }
+        let script_sig = script_sig.into_script();
+        if script_sig.len() > MAX_COINBASE_SCRIPT_SIG_SIZE {
+            return Err(LedgerError::Transaction(format!(
+                "Coinbase scriptSig is {} bytes long, which is more than the maximum {} bytes",
+                script_sig.len(),
+                MAX_COINBASE_SCRIPT_SIG_SIZE
+            )));
+        }
         tracing::trace!("Input script sig {script_sig:?}");
 
+        let reward = self.get_block_subsidy(block_height)? + fees;
+        let extra_value: Amount = options.extra_outputs.iter().map(|o| o.value).sum();
+        let Some(payout) = reward.checked_sub(extra_value) else {
+            return Err(LedgerError::Transaction(format!(
+                "Extra coinbase outputs have {}, which is more than the block reward {}",
+                extra_value, reward
+            )));
+        };
+        let payout_script = match &options.payout_script {
+            Some(script) => script.clone(),
+            None => address.script_pubkey(),
+        };
+
         let mut witness = Witness::new();
         witness.push([0u8; 32]);
         tracing::trace!("Input witness {witness:?}");
 
+        let script_pubkey = Ledger::create_witness_commitment_script(wtxids, [0u8; 32])?;
+
+        Ok(Transaction {
+            version: bitcoin::transaction::Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint {
+                    txid: Txid::all_zeros(),
+                    vout: u32::MAX,
+                },
+                script_sig,
+                sequence: Sequence::ZERO,
+                witness,
+            }],
+            output: [TxOut {
+                value: payout,
+                script_pubkey: payout_script,
+            }]
+            .into_iter()
+            .chain(options.extra_outputs.iter().cloned())
+            .chain([TxOut {
+                value: Amount::from_sat(0),
+                script_pubkey,
+            }])
+            .collect(),
+        })
+    }
+
+    /// Creates the witness commitment output script of a block, using the
+    /// wTXIDs of it's non-coinbase transactions and the witness reserved value
+    /// in it's coinbase transaction's witness.
+    pub fn create_witness_commitment_script(
+        wtxids: Vec<Wtxid>,
+        witness_reserved_value: [u8; 32],
+    ) -> Result<ScriptBuf, LedgerError> {
         // Insert all zeroed wTXID to the list (coinbase transaction).
-        let mut wtxids = wtxids.clone();
+        let mut wtxids = wtxids;
         wtxids.insert(0, Wtxid::all_zeros());
         tracing::trace!("Final wTXIDs: {wtxids:?}");
 
@@ -341,8 +572,8 @@ impl Ledger {
         tracing::trace!("Merkle root of the wTXIDs: {merkle_root:?}");
 
         // Prepare wTXID commitment.
-        let concat = serialize_hex::<TxMerkleNode>(&merkle_root)
-            + "0000000000000000000000000000000000000000000000000000000000000000";
+        let concat =
+            serialize_hex::<TxMerkleNode>(&merkle_root) + &serialize_hex(&witness_reserved_value);
         let mut hex: [u8; 64] = [0; 64];
         hex_to_array(&concat, &mut hex);
         let wtxid_commitment = Hash256::hash(hex.as_slice());
@@ -366,47 +597,27 @@ impl Ledger {
         script_pubkey.push_slice(hex);
         tracing::trace!("Output script pubkey: {:?}", script_pubkey);
 
-        Ok(Transaction {
-            version: bitcoin::transaction::Version::TWO,
-            lock_time: LockTime::ZERO,
-            input: vec![TxIn {
-                previous_output: OutPoint {
-                    txid: Txid::all_zeros(),
-                    vout: u32::MAX,
-                },
-                script_sig,
-                sequence: Sequence::ZERO,
-                witness,
-            }],
-            output: vec![
-                TxOut {
-                    value: Amount::from_sat(crate::utils::BLOCK_REWARD),
-                    script_pubkey: address.script_pubkey(),
-                },
-                TxOut {
-                    value: Amount::from_sat(0),
-                    script_pubkey,
-                },
-            ],
-        })
+        Ok(script_pubkey)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::CoinbaseOptions;
     use crate::{
-        ledger::{self, Ledger},
-        utils::hex_to_array,
+        ledger::{self, errors::LedgerError, Ledger},
+        utils::{hex_to_array, INITIAL_BLOCK_SUBSIDY},
     };
     use bitcoin::{
-        hashes::Hash, opcodes::all::OP_RETURN, Amount, OutPoint, ScriptBuf, TxIn, Txid, Wtxid,
+        absolute::LockTime, hashes::Hash, opcodes::all::OP_RETURN, Amount, OutPoint, ScriptBuf,
+        Sequence, TxIn, Txid, Wtxid,
     };
     use std::str::FromStr;
 
     /// Tests transaction operations over ledger, without any rule checks.
     #[test]
     fn transactions_without_checks() {
-        let ledger = Ledger::new("transactions_without_checks");
+        let ledger = Ledger::new("transactions_without_checks").unwrap();
 
         assert_eq!(ledger._get_transactions().len(), 0);
 
@@ -432,7 +643,7 @@ mod tests {
     /// Tests transaction operations over ledger, with rule checks.
     #[test]
     fn transactions_with_checks() {
-        let ledger = Ledger::new("transactions_with_checks");
+        let ledger = Ledger::new("transactions_with_checks").unwrap();
 
         let credentials = Ledger::generate_credential_from_witness();
         let address = credentials.address;
@@ -473,9 +684,204 @@ mod tests {
         assert_eq!(tx, read_tx);
     }
 
+    #[test]
+    fn check_transaction_double_spend() {
+        let ledger = Ledger::new("check_transaction_double_spend").unwrap();
+
+        let credential = Ledger::generate_credential_from_witness();
+        let address = credential.address;
+
+        let txout = ledger.create_txout(Amount::from_sat(0x45 * 0x45), address.script_pubkey());
+        let tx = ledger.create_transaction(vec![], vec![txout]);
+        let txid = ledger.add_transaction_unconditionally(tx).unwrap();
+        let utxo = OutPoint { txid, vout: 0 };
+
+        // Unknown UTXO.
+        let txin = TxIn {
+            previous_output: OutPoint { txid, vout: 1 },
+            witness: credential.witness.clone().unwrap(),
+            ..Default::default()
+        };
+        let txout = ledger.create_txout(Amount::from_sat(0x45), address.script_pubkey());
+        let tx = ledger.create_transaction(vec![txin], vec![txout]);
+        assert!(matches!(
+            ledger.add_transaction(tx),
+            Err(LedgerError::InputMissingOrSpent(_))
+        ));
+
+        // Same UTXO used twice in a transaction.
+        let txin = TxIn {
+            previous_output: utxo,
+            witness: credential.witness.clone().unwrap(),
+            ..Default::default()
+        };
+        let txout = ledger.create_txout(Amount::from_sat(0x45), address.script_pubkey());
+        let tx = ledger.create_transaction(vec![txin.clone(), txin.clone()], vec![txout]);
+        assert!(ledger.add_transaction(tx).is_err());
+
+        // First spend is valid.
+        let txout = ledger.create_txout(Amount::from_sat(0x45), address.script_pubkey());
+        let tx = ledger.create_transaction(vec![txin.clone()], vec![txout]);
+        ledger.add_transaction(tx).unwrap();
+
+        // A conflicting spend while the first one is in mempool.
+        let txout = ledger.create_txout(Amount::from_sat(0x1F), address.script_pubkey());
+        let tx = ledger.create_transaction(vec![txin.clone()], vec![txout]);
+        assert!(matches!(
+            ledger.add_transaction(tx.clone()),
+            Err(LedgerError::InputMissingOrSpent(outpoint)) if outpoint == utxo
+        ));
+
+        // A conflicting spend after the first one is mined.
+        ledger.mine_block(&address).unwrap();
+        assert!(matches!(
+            ledger.add_transaction(tx),
+            Err(LedgerError::InputMissingOrSpent(outpoint)) if outpoint == utxo
+        ));
+    }
+
+    #[test]
+    fn check_coinbase_maturity() {
+        let ledger = Ledger::new("memory://check_coinbase_maturity").unwrap();
+
+        let credential = Ledger::generate_credential_from_witness();
+        let address = credential.address;
+
+        ledger.mine_block(&address).unwrap();
+        let coinbase = ledger.get_block_with_height(1).unwrap().txdata[0].clone();
+        let utxo = OutPoint {
+            txid: coinbase.compute_txid(),
+            vout: 0,
+        };
+
+        let txin = TxIn {
+            previous_output: utxo,
+            witness: credential.witness.unwrap(),
+            ..Default::default()
+        };
+        let txout = ledger.create_txout(Amount::from_sat(0x45), address.script_pubkey());
+        let tx = ledger.create_transaction(vec![txin], vec![txout]);
+
+        // Coinbase output has 99 confirmations in the next block.
+        for _ in 0..98 {
+            ledger.mine_block(&address).unwrap();
+        }
+        assert!(matches!(
+            ledger.add_transaction(tx.clone()),
+            Err(LedgerError::ImmatureCoinbase(outpoint)) if outpoint == utxo
+        ));
+
+        ledger.mine_block(&address).unwrap();
+        ledger.add_transaction(tx).unwrap();
+    }
+
+    #[test]
+    fn check_transaction_finality() {
+        let ledger = Ledger::new("memory://check_transaction_finality?time=1700000000").unwrap();
+
+        let credential = Ledger::generate_credential_from_witness();
+        let address = credential.address;
+
+        let txout = ledger.create_txout(Amount::from_sat(0x45 * 0x45), address.script_pubkey());
+        let tx = ledger.create_transaction(vec![], vec![txout]);
+        let txid = ledger.add_transaction_unconditionally(tx).unwrap();
+        for _ in 0..11 {
+            ledger.mine_block(&address).unwrap();
+        }
+
+        let spend = |vout: u32, lock_time: LockTime, sequence: Sequence| {
+            let txin = TxIn {
+                previous_output: OutPoint { txid, vout },
+                witness: credential.witness.clone().unwrap(),
+                sequence,
+                ..Default::default()
+            };
+            let txout = ledger.create_txout(Amount::from_sat(0x45), address.script_pubkey());
+            let mut tx = ledger.create_transaction(vec![txin], vec![txout]);
+            tx.lock_time = lock_time;
+
+            tx
+        };
+
+        // Next block is 12. Lock time must be lower than that.
+        let tx = spend(
+            0,
+            LockTime::from_height(12).unwrap(),
+            Sequence::ENABLE_LOCKTIME_NO_RBF,
+        );
+        assert!(ledger.add_transaction(tx.clone()).is_err());
+        // Inputs with the final sequence disable the lock time.
+        let mut final_tx = tx.clone();
+        final_tx.input[0].sequence = Sequence::MAX;
+        ledger.check_transaction_finality(&final_tx).unwrap();
+
+        ledger.mine_block(&address).unwrap();
+        ledger.add_transaction(tx).unwrap();
+        ledger.mine_block(&address).unwrap();
+
+        // Time locks are compared against the median time past, not the time
+        // of the next block.
+        let tip = ledger.get_block_hash(13).unwrap();
+        let median_time_past = ledger.get_median_time_past(tip).unwrap();
+        assert!(ledger.get_block_time(14).unwrap() > median_time_past + 1);
+        let lock_time = |time| LockTime::from_time(time).unwrap();
+        let tx = spend(
+            0,
+            lock_time(median_time_past),
+            Sequence::ENABLE_LOCKTIME_NO_RBF,
+        );
+        assert!(ledger.check_transaction_finality(&tx).is_err());
+        let tx = spend(
+            0,
+            lock_time(median_time_past - 1),
+            Sequence::ENABLE_LOCKTIME_NO_RBF,
+        );
+        ledger.check_transaction_finality(&tx).unwrap();
+
+        // Missing inputs are reported before the lock time, both in the
+        // mempool and in a block.
+        let tx = spend(
+            1,
+            LockTime::from_height(100).unwrap(),
+            Sequence::ENABLE_LOCKTIME_NO_RBF,
+        );
+        assert!(matches!(
+            ledger.add_transaction(tx.clone()),
+            Err(LedgerError::InputMissingOrSpent(_))
+        ));
+        assert!(matches!(
+            ledger.mine_block_with_transactions(&address, vec![tx]),
+            Err(LedgerError::InputMissingOrSpent(_))
+        ));
+    }
+
+    #[test]
+    fn check_coinbase_maturity_disabled() {
+        let ledger =
+            Ledger::new("memory://check_coinbase_maturity_disabled?coinbase_maturity=0").unwrap();
+
+        let credential = Ledger::generate_credential_from_witness();
+        let address = credential.address;
+
+        ledger.mine_block(&address).unwrap();
+        let coinbase = ledger.get_block_with_height(1).unwrap().txdata[0].clone();
+
+        let txin = TxIn {
+            previous_output: OutPoint {
+                txid: coinbase.compute_txid(),
+                vout: 0,
+            },
+            witness: credential.witness.unwrap(),
+            ..Default::default()
+        };
+        let txout = ledger.create_txout(Amount::from_sat(0x45), address.script_pubkey());
+        let tx = ledger.create_transaction(vec![txin], vec![txout]);
+        ledger.add_transaction(tx).unwrap();
+    }
+
     #[test]
     fn calculate_transaction_input_value() {
-        let ledger = Ledger::new("calculate_transaction_input_value");
+        let ledger = Ledger::new("calculate_transaction_input_value").unwrap();
 
         let address = Ledger::generate_address_from_witness();
 
@@ -504,7 +910,7 @@ mod tests {
 
     #[test]
     fn calculate_transaction_output_value() {
-        let ledger = Ledger::new("calculate_transaction_output_value");
+        let ledger = Ledger::new("calculate_transaction_output_value").unwrap();
 
         let txout1 = ledger.create_txout(Amount::from_sat(0x45), ScriptBuf::new());
         let tx = ledger.create_transaction(vec![], vec![txout1.clone()]);
@@ -524,7 +930,7 @@ mod tests {
     #[test]
     #[should_panic]
     fn check_transaction_wiht_low_input_value() {
-        let ledger = Ledger::new("check_transaction_wiht_low_input_value");
+        let ledger = Ledger::new("check_transaction_wiht_low_input_value").unwrap();
 
         let txout = ledger.create_txout(Amount::from_sat(0x45), ScriptBuf::new());
         let tx = ledger.create_transaction(vec![], vec![txout.clone()]);
@@ -539,14 +945,14 @@ mod tests {
     #[test]
     #[should_panic]
     fn get_transaction_non_existing() {
-        let ledger = Ledger::new("get_transaction_non_existing");
+        let ledger = Ledger::new("get_transaction_non_existing").unwrap();
         ledger.get_transaction(Txid::all_zeros()).unwrap();
     }
 
     #[test]
     #[should_panic]
     fn get_transaction_block_height_non_existing() {
-        let ledger = Ledger::new("get_transaction_block_height_non_existing");
+        let ledger = Ledger::new("get_transaction_block_height_non_existing").unwrap();
         ledger
             .get_transaction_block_height(&Txid::all_zeros())
             .unwrap();
@@ -554,7 +960,7 @@ mod tests {
 
     #[test]
     fn create_coinbase_transaction() {
-        let ledger = Ledger::new("create_coinbase_transaction");
+        let ledger = Ledger::new("create_coinbase_transaction").unwrap();
 
         let address = ledger::Ledger::generate_credential().address;
         let wtxids: Vec<Wtxid> = vec![
@@ -567,8 +973,18 @@ mod tests {
         ];
 
         let tx = ledger
-            .create_coinbase_transaction(&address, wtxids)
+            .create_coinbase_transaction(
+                &address,
+                wtxids,
+                1,
+                Amount::from_sat(0x45),
+                &CoinbaseOptions::default(),
+            )
             .unwrap();
+        assert_eq!(
+            tx.output[0].value,
+            Amount::from_sat(INITIAL_BLOCK_SUBSIDY + 0x45)
+        );
 
         assert_eq!(tx.input.len(), 1);
         assert_eq!(
@@ -597,4 +1013,82 @@ mod tests {
             expected_script_pubkey
         );
     }
+
+    #[test]
+    fn coinbase_height_encoding() {
+        let ledger = Ledger::new("memory://coinbase_height_encoding").unwrap();
+        let address = ledger::Ledger::generate_credential().address;
+
+        for (height, script_sig) in [
+            (1, vec![0x51, 0x00]),
+            (16, vec![0x60, 0x00]),
+            (17, vec![0x01, 0x11]),
+            (0x80, vec![0x02, 0x80, 0x00]),
+            (0x1234, vec![0x02, 0x34, 0x12]),
+            (500_000, vec![0x03, 0x20, 0xa1, 0x07]),
+        ] {
+            let tx = ledger
+                .create_coinbase_transaction(
+                    &address,
+                    vec![],
+                    height,
+                    Amount::ZERO,
+                    &CoinbaseOptions::default(),
+                )
+                .unwrap();
+
+            assert_eq!(tx.input[0].script_sig.to_bytes(), script_sig);
+        }
+    }
+
+    #[test]
+    fn custom_coinbase_transaction() {
+        let ledger = Ledger::new("memory://custom_coinbase_transaction").unwrap();
+        let address = ledger::Ledger::generate_credential().address;
+
+        let payout_script = ScriptBuf::from_bytes(vec![0x51]);
+        let extra_output = ledger.create_txout(Amount::from_sat(0x45), ScriptBuf::new());
+        let options = CoinbaseOptions {
+            script_sig_data: b"mock pool".to_vec(),
+            payout_script: Some(payout_script.clone()),
+            extra_outputs: vec![extra_output.clone()],
+        };
+
+        let tx = ledger
+            .create_coinbase_transaction(&address, vec![], 17, Amount::ZERO, &options)
+            .unwrap();
+        assert_eq!(
+            tx.input[0].script_sig.to_bytes(),
+            [&[0x01, 0x11, 0x09][..], b"mock pool"].concat()
+        );
+        assert_eq!(tx.output.len(), 3);
+        assert_eq!(tx.output[0].script_pubkey, payout_script);
+        assert_eq!(
+            tx.output[0].value,
+            Amount::from_sat(INITIAL_BLOCK_SUBSIDY - 0x45)
+        );
+        assert_eq!(tx.output[1], extra_output);
+        assert!(tx.output[2].script_pubkey.is_op_return());
+
+        // Extra outputs can't claim more than the block reward.
+        let options = CoinbaseOptions {
+            extra_outputs: vec![ledger.create_txout(
+                Amount::from_sat(INITIAL_BLOCK_SUBSIDY + 1),
+                ScriptBuf::new(),
+            )],
+            ..Default::default()
+        };
+        assert!(ledger
+            .create_coinbase_transaction(&address, vec![], 1, Amount::ZERO, &options)
+            .is_err());
+
+        // ScriptSig can't be longer than 100 bytes.
+        let options = CoinbaseOptions {
+            script_sig_data: vec![0x45; 100],
+            ..Default::default()
+        };
+        assert!(ledger
+            .create_coinbase_transaction(&address, vec![], 1, Amount::ZERO, &options)
+            .is_err());
+    }
 }
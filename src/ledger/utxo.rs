@@ -1,25 +1,118 @@
 //! # UTXO Management
+//!
+//! UTXO set holds every spendable output with it's value, script, creation
+//! height and coinbase flag. So, checking a spend doesn't need decoding the
+//! transaction that created the output.
 
 use super::{errors::LedgerError, Ledger};
-use bitcoin::OutPoint;
-use rusqlite::params;
+use bitcoin::{Amount, OutPoint, ScriptBuf, Transaction, TxOut, Txid};
+use rusqlite::{params, Row};
+use std::str::FromStr;
+
+/// An unspent transaction output.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Utxo {
+    pub outpoint: OutPoint,
+    pub txout: TxOut,
+    /// Height of the block that created this output. Outputs of the mempool
+    /// transactions have the next block's height.
+    pub height: u32,
+    /// Is this output created by a coinbase transaction?
+    pub coinbase: bool,
+}
+
+impl Utxo {
+    /// Creates the UTXO for `transaction`'s output at `vout`, if it exists.
+    pub fn from_transaction(transaction: &Transaction, vout: u32, height: u32) -> Option<Self> {
+        let txout = transaction.output.get(vout as usize)?;
+
+        Some(Self {
+            outpoint: OutPoint {
+                txid: transaction.compute_txid(),
+                vout,
+            },
+            txout: txout.to_owned(),
+            height,
+            coinbase: transaction.is_coinbase(),
+        })
+    }
+
+    /// Reads a UTXO from a row of columns `txid`, `vout`, `value`,
+    /// `script_pubkey`, `height` and `coinbase`, in that order.
+    pub fn from_row(row: &Row) -> Result<Self, rusqlite::Error> {
+        let txid: String = row.get(0)?;
+
+        Ok(Self {
+            outpoint: OutPoint {
+                txid: Txid::from_str(&txid).unwrap(),
+                vout: row.get(1)?,
+            },
+            txout: TxOut {
+                value: Amount::from_sat(row.get(2)?),
+                script_pubkey: ScriptBuf::from_bytes(row.get(3)?),
+            },
+            height: row.get(4)?,
+            coinbase: row.get(5)?,
+        })
+    }
+}
 
 impl Ledger {
-    pub fn add_utxo(&self, utxo: OutPoint) -> Result<(), LedgerError> {
+    pub fn add_utxo(&self, utxo: &Utxo) -> Result<(), LedgerError> {
         if let Err(e) = self.database.lock().unwrap().execute(
-            "INSERT INTO utxos (txid, vout) VALUES (?1, ?2)",
-            params![utxo.txid.to_string(), utxo.vout],
+            "INSERT INTO utxos (txid, vout, value, script_pubkey, height, coinbase) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                utxo.outpoint.txid.to_string(),
+                utxo.outpoint.vout,
+                utxo.txout.value.to_sat(),
+                utxo.txout.script_pubkey.as_bytes(),
+                utxo.height,
+                utxo.coinbase
+            ],
         ) {
             return Err(LedgerError::Utxo(format!(
                 "Couldn't add utxo {:?} to ledger: {}",
-                utxo, e
+                utxo.outpoint, e
             )));
         };
-        tracing::trace!("UTXO {utxo:?} saved");
+        tracing::trace!("UTXO {:?} saved", utxo.outpoint);
 
         Ok(())
     }
 
+    /// Returns the UTXO at `outpoint`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`LedgerError::InputMissingOrSpent`] if UTXO is either spent or
+    /// doesn't exist.
+    pub fn get_utxo(&self, outpoint: OutPoint) -> Result<Utxo, LedgerError> {
+        self.database
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT txid, vout, value, script_pubkey, height, coinbase FROM utxos WHERE txid = ?1 AND vout = ?2",
+                params![outpoint.txid.to_string(), outpoint.vout],
+                Utxo::from_row,
+            )
+            .map_err(|_| LedgerError::InputMissingOrSpent(outpoint))
+    }
+
+    /// Creates a UTXO for `outpoint`, using the transaction that created it.
+    /// Unlike [`Ledger::get_utxo`], this works for spent outputs too.
+    pub fn get_utxo_from_transaction(&self, outpoint: OutPoint) -> Result<Utxo, LedgerError> {
+        let transaction = self.get_transaction(outpoint.txid)?;
+        let height = self.get_transaction_block_height(&outpoint.txid)?;
+
+        match Utxo::from_transaction(&transaction, outpoint.vout, height) {
+            Some(utxo) => Ok(utxo),
+            None => Err(LedgerError::Utxo(format!(
+                "Transaction {} doesn't have an output at index {}",
+                outpoint.txid, outpoint.vout
+            ))),
+        }
+    }
+
     pub fn is_utxo_spent(&self, utxo: OutPoint) -> bool {
         self.database
             .lock()
@@ -50,24 +143,73 @@ impl Ledger {
 
 #[cfg(test)]
 mod tests {
-    use crate::ledger::Ledger;
-    use bitcoin::{hashes::Hash, OutPoint, Txid};
+    use super::Utxo;
+    use crate::ledger::{errors::LedgerError, Ledger};
+    use bitcoin::{hashes::Hash, Amount, OutPoint, ScriptBuf, TxOut, Txid};
 
     #[test]
     fn basic_add_remove_utxo() {
-        let ledger = Ledger::new("basic_add_remove_utxo");
+        let ledger = Ledger::new("basic_add_remove_utxo").unwrap();
 
-        let utxo = OutPoint {
-            txid: Txid::all_zeros(),
-            vout: 0x45,
+        let utxo = Utxo {
+            outpoint: OutPoint {
+                txid: Txid::all_zeros(),
+                vout: 0x45,
+            },
+            txout: TxOut {
+                value: Amount::from_sat(0x1F),
+                script_pubkey: ScriptBuf::from_bytes(vec![0x51]),
+            },
+            height: 3,
+            coinbase: true,
         };
 
-        assert!(ledger.is_utxo_spent(utxo));
+        assert!(ledger.is_utxo_spent(utxo.outpoint));
+        assert!(matches!(
+            ledger.get_utxo(utxo.outpoint),
+            Err(LedgerError::InputMissingOrSpent(_))
+        ));
+
+        ledger.add_utxo(&utxo).unwrap();
+        assert!(!ledger.is_utxo_spent(utxo.outpoint));
+        assert_eq!(ledger.get_utxo(utxo.outpoint).unwrap(), utxo);
+
+        ledger.remove_utxo(utxo.outpoint).unwrap();
+        assert!(ledger.is_utxo_spent(utxo.outpoint));
+    }
+
+    #[test]
+    fn transaction_utxos() {
+        let ledger = Ledger::new("transaction_utxos").unwrap();
+        let address = Ledger::generate_credential_from_witness().address;
+
+        ledger.mine_block(&address).unwrap();
+        let coinbase = ledger.get_block_with_height(1).unwrap().txdata[0].clone();
+        let outpoint = OutPoint {
+            txid: coinbase.compute_txid(),
+            vout: 0,
+        };
+
+        let utxo = ledger.get_utxo(outpoint).unwrap();
+        assert_eq!(utxo.txout, coinbase.output[0]);
+        assert_eq!(utxo.height, 1);
+        assert!(utxo.coinbase);
+
+        let txout = ledger.create_txout(Amount::from_sat(0x45), address.script_pubkey());
+        let tx = ledger.create_transaction(vec![ledger.create_txin(outpoint.txid, 0)], vec![txout]);
+        let txid = ledger.add_transaction_unconditionally(tx).unwrap();
 
-        ledger.add_utxo(utxo).unwrap();
-        assert!(!ledger.is_utxo_spent(utxo));
+        // Mempool transactions' outputs have the next block's height.
+        let utxo = ledger.get_utxo(OutPoint { txid, vout: 0 }).unwrap();
+        assert_eq!(utxo.txout.value, Amount::from_sat(0x45));
+        assert_eq!(utxo.height, 2);
+        assert!(!utxo.coinbase);
 
-        ledger.remove_utxo(utxo).unwrap();
-        assert!(ledger.is_utxo_spent(utxo));
+        // Spent outputs can still be read from their transaction.
+        assert!(ledger.get_utxo(outpoint).is_err());
+        assert_eq!(
+            ledger.get_utxo_from_transaction(outpoint).unwrap().txout,
+            coinbase.output[0]
+        );
     }
 }
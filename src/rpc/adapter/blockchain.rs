@@ -21,14 +21,16 @@ pub fn getblock(
     let blockhash = _decode_from_hex::<BlockHash>(blockhash)?;
     tracing::trace!("Decoded block hash: {blockhash:?}");
 
-    let block = client.get_block(&blockhash)?;
-    let encoded = encode_to_hex(&block);
-    tracing::trace!("Block: {block:?}");
-    tracing::trace!("Encoded value: {encoded}");
-
     match verbosity {
-        Some(0) => Ok(encoded),
-        None | Some(1) => Ok(serde_json::to_string(&block)?),
+        Some(0) => {
+            let block = client.get_block(&blockhash)?;
+            let encoded = encode_to_hex(&block);
+            tracing::trace!("Block: {block:?}");
+            tracing::trace!("Encoded value: {encoded}");
+
+            Ok(encoded)
+        }
+        None | Some(1) => Ok(serde_json::to_string(&client.get_block_info(&blockhash)?)?),
         _ => Err(Error::UnexpectedStructure),
     }
 }
@@ -49,11 +51,12 @@ pub fn getblockheader(
     verbose: Option<bool>,
 ) -> Result<String, Error> {
     let blockhash = _decode_from_hex::<BlockHash>(blockhash)?;
-    let header = client.get_block_header(&blockhash)?;
 
     match verbose {
-        None | Some(true) => Ok(serde_json::to_string(&header).unwrap()),
-        Some(false) => Ok(encode_to_hex(&header)),
+        None | Some(true) => Ok(serde_json::to_string(
+            &client.get_block_header_info(&blockhash)?,
+        )?),
+        Some(false) => Ok(encode_to_hex(&client.get_block_header(&blockhash)?)),
     }
 }
 
@@ -78,17 +81,25 @@ pub fn gettxout(
 
 #[cfg(test)]
 mod tests {
-    use crate::{Client, RpcApiWrapper};
+    use crate::{
+        utils::{_decode_from_hex, encode_to_hex},
+        Client, RpcApiWrapper,
+    };
+    use bitcoin::block::Header;
     use bitcoin::consensus::Decodable;
     use bitcoin::BlockHash;
-    use bitcoincore_rpc::RpcApi;
+    use bitcoincore_rpc::{json, RpcApi};
 
     #[test]
     fn getbestblockhash() {
         let client = Client::new("getbestblockhash", bitcoincore_rpc::Auth::None).unwrap();
 
-        // No blocks created, no blocks are available to return.
-        assert!(super::getbestblockhash(&client).is_err());
+        // No blocks created, genesis block is the best block.
+        let genesis = super::getbestblockhash(&client).unwrap();
+        assert_eq!(
+            _decode_from_hex::<BlockHash>(genesis).unwrap(),
+            client.get_block_hash(0).unwrap()
+        );
 
         let address = client.get_new_address(None, None).unwrap().assume_checked();
         client.generate_to_address(101, &address).unwrap();
@@ -109,4 +120,29 @@ mod tests {
 
         assert_eq!(super::getblockcount(&client).unwrap(), 101);
     }
+
+    #[test]
+    fn getblockheader_and_getblock() {
+        let client =
+            Client::new("getblockheader_and_getblock", bitcoincore_rpc::Auth::None).unwrap();
+        let address = client.get_new_address(None, None).unwrap().assume_checked();
+        let block_hash = client.generate_to_address(1, &address).unwrap()[0];
+        let encoded_hash = encode_to_hex(&block_hash);
+
+        let header = super::getblockheader(&client, encoded_hash.clone(), Some(false)).unwrap();
+        assert_eq!(
+            _decode_from_hex::<Header>(header).unwrap(),
+            client.get_block_header(&block_hash).unwrap()
+        );
+
+        let header_info = super::getblockheader(&client, encoded_hash.clone(), None).unwrap();
+        let header_info: json::GetBlockHeaderResult = serde_json::from_str(&header_info).unwrap();
+        assert_eq!(header_info.height, 1);
+        assert_eq!(header_info.confirmations, 1);
+
+        let block_info = super::getblock(&client, encoded_hash, Some(1)).unwrap();
+        let block_info: json::GetBlockResult = serde_json::from_str(&block_info).unwrap();
+        assert_eq!(block_info.hash, block_hash);
+        assert_eq!(block_info.n_tx, 1);
+    }
 }
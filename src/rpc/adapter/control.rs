@@ -0,0 +1,24 @@
+//! # Control RPCs
+
+use crate::Client;
+use bitcoincore_rpc::Error;
+
+pub fn setmocktime(client: &Client, timestamp: u64) -> Result<(), Error> {
+    client.set_mock_time(timestamp)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Client, RpcApiWrapper};
+    use bitcoincore_rpc::RpcApi;
+
+    #[test]
+    fn setmocktime() {
+        let client = Client::new("memory://setmocktime", bitcoincore_rpc::Auth::None).unwrap();
+        let address = client.get_new_address(None, None).unwrap().assume_checked();
+
+        super::setmocktime(&client, 2_000_000_000).unwrap();
+        let hash = client.generate_to_address(1, &address).unwrap()[0];
+        assert_eq!(client.get_block_header(&hash).unwrap().time, 2_000_000_000);
+    }
+}
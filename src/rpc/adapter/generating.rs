@@ -1,10 +1,19 @@
 //! # Generating RPCs
 
-use crate::{utils::encode_to_hex, Client};
-use bitcoin::Address;
+use crate::{
+    utils::{_decode_from_hex, encode_to_hex},
+    Client,
+};
+use bitcoin::{Address, Transaction, Txid};
 use bitcoincore_rpc::{Error, RpcApi};
+use serde::{Deserialize, Serialize};
 use std::str::FromStr;
 
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct GenerateblockResult {
+    pub hash: String,
+}
+
 pub fn generatetoaddress(
     client: &Client,
     nblocks: usize,
@@ -21,7 +30,8 @@ pub fn generatetoaddress(
             ));
         }
     }
-    .assume_checked();
+    .require_network(client.get_network()?)
+    .map_err(|e| Error::ReturnedError(e.to_string()))?;
 
     tracing::trace!("Address converted: {address}");
 
@@ -31,9 +41,49 @@ pub fn generatetoaddress(
     Ok(hashes)
 }
 
+/// Mines a block with given transactions. Transactions can be either TXIDs of
+/// mempool transactions or raw transactions in hex.
+pub fn generateblock(
+    client: &Client,
+    output: String,
+    transactions: Vec<String>,
+    submit: Option<bool>,
+) -> Result<GenerateblockResult, Error> {
+    if submit == Some(false) {
+        return Err(Error::ReturnedError(
+            "Blocks that are not submitted are not supported".to_string(),
+        ));
+    }
+
+    let address = Address::from_str(&output)
+        .map_err(|e| Error::ReturnedError(e.to_string()))?
+        .require_network(client.get_network()?)
+        .map_err(|e| Error::ReturnedError(e.to_string()))?;
+
+    let transactions = transactions
+        .into_iter()
+        .map(|transaction| match Txid::from_str(&transaction) {
+            Ok(txid) => client.get_raw_transaction(&txid, None),
+            Err(_) => _decode_from_hex::<Transaction>(transaction),
+        })
+        .collect::<Result<Vec<Transaction>, Error>>()?;
+    tracing::trace!("Transactions of the block: {transactions:?}");
+
+    let hash = client.generate_block(&address, &transactions)?;
+
+    Ok(GenerateblockResult {
+        hash: encode_to_hex(&hash),
+    })
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::{Client, RpcApiWrapper};
+    use crate::{
+        ledger::Ledger,
+        utils::{_decode_from_hex, encode_to_hex},
+        Client, RpcApiWrapper,
+    };
+    use bitcoin::{absolute, transaction, Amount, BlockHash, OutPoint, Transaction, TxIn, TxOut};
     use bitcoincore_rpc::RpcApi;
 
     #[test]
@@ -47,4 +97,71 @@ mod tests {
 
         assert_eq!(client.get_block_count().unwrap(), 101);
     }
+
+    #[test]
+    fn generatetoaddress_with_network() {
+        let client = Client::new(
+            "memory://generatetoaddress_with_network?network=testnet",
+            bitcoincore_rpc::Auth::None,
+        )
+        .unwrap();
+
+        let regtest_address = Ledger::generate_address_from_witness();
+        assert!(super::generatetoaddress(&client, 1, regtest_address.to_string(), None).is_err());
+
+        let address = client.get_new_address(None, None).unwrap().assume_checked();
+        super::generatetoaddress(&client, 1, address.to_string(), None).unwrap();
+        assert_eq!(client.get_block_count().unwrap(), 1);
+    }
+
+    #[test]
+    fn generateblock() {
+        let client = Client::new("generateblock", bitcoincore_rpc::Auth::None).unwrap();
+        let credential = Ledger::generate_credential_from_witness();
+        let address = credential.address.clone();
+        client.generate_to_address(102, &address).unwrap();
+
+        let txs: Vec<_> = (1..=2)
+            .map(|height| {
+                let coinbase = &client
+                    .get_block(&client.get_block_hash(height).unwrap())
+                    .unwrap()
+                    .txdata[0];
+
+                Transaction {
+                    version: transaction::Version::TWO,
+                    lock_time: absolute::LockTime::ZERO,
+                    input: vec![TxIn {
+                        previous_output: OutPoint {
+                            txid: coinbase.compute_txid(),
+                            vout: 0,
+                        },
+                        witness: credential.witness.clone().unwrap(),
+                        ..Default::default()
+                    }],
+                    output: vec![TxOut {
+                        value: Amount::from_sat(0x45),
+                        script_pubkey: address.script_pubkey(),
+                    }],
+                }
+            })
+            .collect();
+        let txid = client.send_raw_transaction(&txs[0]).unwrap();
+
+        let result = super::generateblock(
+            &client,
+            address.to_string(),
+            vec![txid.to_string(), encode_to_hex(&txs[1])],
+            None,
+        )
+        .unwrap();
+        let hash = _decode_from_hex::<BlockHash>(result.hash).unwrap();
+        assert_eq!(client.get_block(&hash).unwrap().txdata[1..], txs);
+
+        assert!(super::generateblock(&client, address.to_string(), vec![], Some(false)).is_err());
+        assert!(
+            super::generateblock(&client, address.to_string(), vec!["00".to_string()], None)
+                .is_err()
+        );
+    }
 }
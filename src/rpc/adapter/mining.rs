@@ -0,0 +1,126 @@
+//! # Mining RPCs
+
+use crate::Client;
+use bitcoincore_rpc::{json, Error, RpcApi};
+use serde::{Deserialize, Serialize};
+
+/// `template_request` parameter of `getblocktemplate`. Unknown rules and
+/// capabilities are ignored, like Bitcoin Core does.
+///
+/// Capabilities are the features that the caller supports. Template always has
+/// a `coinbasevalue` and doesn't use any optional feature, like `longpoll`. So
+/// a caller that only supports `coinbasetxn` is rejected.
+#[derive(Clone, PartialEq, Eq, Debug, Default, Deserialize, Serialize)]
+pub struct GetblocktemplateRequest {
+    #[serde(default)]
+    pub mode: Option<String>,
+    #[serde(default)]
+    pub rules: Vec<String>,
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+}
+
+pub fn getblocktemplate(
+    client: &Client,
+    template_request: Option<GetblocktemplateRequest>,
+) -> Result<json::GetBlockTemplateResult, Error> {
+    let template_request = template_request.unwrap_or_default();
+
+    match template_request.mode.as_deref() {
+        None | Some("template") => (),
+        Some(mode) => {
+            return Err(Error::ReturnedError(format!(
+                "Mode {mode} is not supported"
+            )))
+        }
+    }
+
+    let capabilities = &template_request.capabilities;
+    if capabilities.iter().any(|c| c == "coinbasetxn")
+        && !capabilities.iter().any(|c| c == "coinbasevalue")
+    {
+        return Err(Error::ReturnedError(
+            "Only templates with coinbasevalue are supported; coinbasetxn is not".to_string(),
+        ));
+    }
+
+    let rules: Vec<json::GetBlockTemplateRules> = template_request
+        .rules
+        .iter()
+        .filter_map(|rule| serde_json::from_value(serde_json::Value::String(rule.clone())).ok())
+        .collect();
+
+    client.get_block_template(json::GetBlockTemplateModes::Template, &rules, &[])
+}
+
+pub fn submitblock(client: &Client, hexdata: String, _dummy: Option<String>) -> Result<(), Error> {
+    client.submit_block_hex(&hexdata)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GetblocktemplateRequest;
+    use crate::{ledger::Ledger, Client, RpcApiWrapper};
+    use bitcoin::consensus::encode::serialize_hex;
+    use bitcoincore_rpc::RpcApi;
+
+    #[test]
+    fn submitblock() {
+        let client = Client::new("memory://submitblock", bitcoincore_rpc::Auth::None).unwrap();
+        let address = Ledger::generate_credential_from_witness().address;
+
+        // Blocks of another ledger with the same genesis block can be
+        // submitted.
+        let miner = Client::new("memory://submitblock_miner", bitcoincore_rpc::Auth::None).unwrap();
+        let hash = miner.generate_to_address(1, &address).unwrap()[0];
+        let block = miner.get_block(&hash).unwrap();
+
+        assert!(super::submitblock(&client, "00".to_string(), None).is_err());
+
+        super::submitblock(&client, serialize_hex(&block), None).unwrap();
+        assert_eq!(client.get_best_block_hash().unwrap(), hash);
+
+        assert!(super::submitblock(&client, serialize_hex(&block), None).is_err());
+    }
+
+    #[test]
+    fn getblocktemplate() {
+        let client = Client::new("memory://getblocktemplate", bitcoincore_rpc::Auth::None).unwrap();
+        let address = Ledger::generate_credential_from_witness().address;
+        client.generate_to_address(1, &address).unwrap();
+
+        // SegWit rule is required.
+        assert!(super::getblocktemplate(&client, None).is_err());
+
+        let request = GetblocktemplateRequest {
+            rules: vec!["segwit".to_string(), "unknown".to_string()],
+            capabilities: vec!["longpoll".to_string()],
+            ..Default::default()
+        };
+        let template = super::getblocktemplate(&client, Some(request.clone())).unwrap();
+        assert_eq!(
+            template.previous_block_hash,
+            client.get_best_block_hash().unwrap()
+        );
+        assert_eq!(template.height, 2);
+        assert!(template.transactions.is_empty());
+
+        // Template can't have a coinbase transaction instead of a value.
+        let coinbase_txn = GetblocktemplateRequest {
+            capabilities: vec!["coinbasetxn".to_string()],
+            ..request.clone()
+        };
+        assert!(super::getblocktemplate(&client, Some(coinbase_txn.clone())).is_err());
+        let coinbase_value = GetblocktemplateRequest {
+            capabilities: vec!["coinbasetxn".to_string(), "coinbasevalue".to_string()],
+            ..coinbase_txn
+        };
+        super::getblocktemplate(&client, Some(coinbase_value)).unwrap();
+
+        let request = GetblocktemplateRequest {
+            mode: Some("proposal".to_string()),
+            ..request
+        };
+        assert!(super::getblocktemplate(&client, Some(request)).is_err());
+    }
+}
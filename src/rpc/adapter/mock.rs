@@ -0,0 +1,34 @@
+//! # Mock RPCs
+//!
+//! RPCs that are not available in Bitcoin, but are useful for testing.
+
+use crate::Client;
+use bitcoincore_rpc::Error;
+
+pub fn snapshot(client: &Client) -> Result<u32, Error> {
+    client.snapshot()
+}
+
+pub fn restore(client: &Client, id: u32) -> Result<(), Error> {
+    client.restore(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Client, RpcApiWrapper};
+    use bitcoincore_rpc::RpcApi;
+
+    #[test]
+    fn snapshot_and_restore() {
+        let client =
+            Client::new("memory://snapshot_and_restore", bitcoincore_rpc::Auth::None).unwrap();
+        let address = client.get_new_address(None, None).unwrap().assume_checked();
+
+        let id = super::snapshot(&client).unwrap();
+        client.generate_to_address(101, &address).unwrap();
+        assert_eq!(client.get_block_count().unwrap(), 101);
+
+        super::restore(&client, id).unwrap();
+        assert_eq!(client.get_block_count().unwrap(), 0);
+    }
+}
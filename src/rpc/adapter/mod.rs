@@ -4,11 +4,17 @@
 //! RPC interface.
 
 mod blockchain;
+mod control;
 mod generating;
+mod mining;
+mod mock;
 mod rawtransactions;
 mod wallet;
 
 pub use blockchain::*;
+pub use control::*;
 pub use generating::*;
+pub use mining::*;
+pub use mock::*;
 pub use rawtransactions::*;
 pub use wallet::*;
@@ -56,7 +56,8 @@ pub fn sendtoaddress(
             return Err(bitcoincore_rpc::Error::ReturnedError(e.to_string()));
         }
     }
-    .assume_checked();
+    .require_network(client.get_network()?)
+    .map_err(|e| Error::ReturnedError(e.to_string()))?;
     let amount = match Amount::from_float_in(amount, bitcoin::Denomination::Bitcoin) {
         Ok(a) => a,
         Err(e) => {
@@ -6,7 +6,7 @@
 use super::adapter::{self, GetrawtransactionReturn};
 use crate::Client;
 use bitcoin::{BlockHash, Txid};
-use bitcoincore_rpc::json::GetTransactionResult;
+use bitcoincore_rpc::json::{GetBlockTemplateResult, GetTransactionResult};
 use jsonrpsee::core::async_trait;
 use jsonrpsee::proc_macros::rpc;
 use jsonrpsee::types::ErrorObjectOwned;
@@ -52,6 +52,30 @@ pub trait Rpc {
         maxtries: Option<usize>,
     ) -> Result<Vec<String>, ErrorObjectOwned>;
 
+    #[method(name = "generateblock")]
+    async fn generateblock(
+        &self,
+        output: String,
+        transactions: Vec<String>,
+        submit: Option<bool>,
+    ) -> Result<adapter::GenerateblockResult, ErrorObjectOwned>;
+
+    #[method(name = "submitblock")]
+    async fn submitblock(
+        &self,
+        hexdata: String,
+        dummy: Option<String>,
+    ) -> Result<(), ErrorObjectOwned>;
+
+    #[method(name = "getblocktemplate")]
+    async fn getblocktemplate(
+        &self,
+        template_request: Option<adapter::GetblocktemplateRequest>,
+    ) -> Result<GetBlockTemplateResult, ErrorObjectOwned>;
+
+    #[method(name = "setmocktime")]
+    async fn setmocktime(&self, timestamp: u64) -> Result<(), ErrorObjectOwned>;
+
     #[method(name = "getrawtransaction")]
     async fn getrawtransaction(
         &self,
@@ -111,6 +135,12 @@ pub trait Rpc {
         prevtxs: Option<String>,
         sighashtype: Option<String>,
     ) -> Result<bitcoincore_rpc::json::SignRawTransactionResult, ErrorObjectOwned>;
+
+    #[method(name = "snapshot")]
+    async fn snapshot(&self) -> Result<u32, ErrorObjectOwned>;
+
+    #[method(name = "restore")]
+    async fn restore(&self, id: u32) -> Result<(), ErrorObjectOwned>;
 }
 
 #[async_trait]
@@ -161,6 +191,34 @@ impl RpcServer for Client {
         to_jsonrpsee_error(adapter::generatetoaddress(self, nblocks, address, maxtries))
     }
 
+    async fn generateblock(
+        &self,
+        output: String,
+        transactions: Vec<String>,
+        submit: Option<bool>,
+    ) -> Result<adapter::GenerateblockResult, ErrorObjectOwned> {
+        to_jsonrpsee_error(adapter::generateblock(self, output, transactions, submit))
+    }
+
+    async fn submitblock(
+        &self,
+        hexdata: String,
+        dummy: Option<String>,
+    ) -> Result<(), ErrorObjectOwned> {
+        to_jsonrpsee_error(adapter::submitblock(self, hexdata, dummy))
+    }
+
+    async fn getblocktemplate(
+        &self,
+        template_request: Option<adapter::GetblocktemplateRequest>,
+    ) -> Result<GetBlockTemplateResult, ErrorObjectOwned> {
+        to_jsonrpsee_error(adapter::getblocktemplate(self, template_request))
+    }
+
+    async fn setmocktime(&self, timestamp: u64) -> Result<(), ErrorObjectOwned> {
+        to_jsonrpsee_error(adapter::setmocktime(self, timestamp))
+    }
+
     async fn getrawtransaction(
         &self,
         txid: String,
@@ -250,6 +308,14 @@ impl RpcServer for Client {
             sighashtype,
         ))
     }
+
+    async fn snapshot(&self) -> Result<u32, ErrorObjectOwned> {
+        to_jsonrpsee_error(adapter::snapshot(self))
+    }
+
+    async fn restore(&self, id: u32) -> Result<(), ErrorObjectOwned> {
+        to_jsonrpsee_error(adapter::restore(self, id))
+    }
 }
 
 /// Helper for converting ledger error to [`jsonrpsee`] error.
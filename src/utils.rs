@@ -11,12 +11,11 @@ use bitcoin::{
     hashes::{sha256, Hash},
     TxMerkleNode,
 };
-use rs_merkle::{Hasher, MerkleTree};
+use rs_merkle::Hasher;
 use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
 
-/// Block reward is fixed to 50 BTC, regardless of which and how many blocks are
-/// generated.
-pub(crate) const BLOCK_REWARD: u64 = 5_000_000_000;
+/// Block subsidy of the first blocks, before any halving: 50 BTC.
+pub(crate) const INITIAL_BLOCK_SUBSIDY: u64 = 5_000_000_000;
 
 /// Bitcoin merkle root hashing algorithm.
 #[derive(Clone)]
@@ -30,8 +29,9 @@ impl Hasher for Hash256 {
     }
 }
 
-/// Calculates given inputs merkle root. If input number is odd, last input will
-/// be added to the list again.
+/// Calculates given inputs merkle root, like Bitcoin does: If a level of the
+/// tree has an odd number of nodes, last node is hashed with itself. Root of a
+/// single input is the input itself.
 ///
 /// This merkle root calculator is useful for TXID and wTXID merkle roots.
 ///
@@ -42,7 +42,7 @@ pub fn calculate_merkle_root<T>(inputs: Vec<T>) -> Result<TxMerkleNode, LedgerEr
 where
     T: Encodable,
 {
-    let mut leaves: Vec<_> = inputs
+    let mut level: Vec<_> = inputs
         .iter()
         .map(|input| {
             let mut hex: Vec<u8> = Vec::new();
@@ -55,20 +55,21 @@ where
         })
         .collect();
 
-    // If there are odd numbered transactions, we must concatenate and hash
-    // with itself. Hashing is done by the MerkleTree library. We only need
-    // to add an extra TXID/wTXID to the list.
-    let len = leaves.len();
-    if len % 2 == 1 {
-        leaves.push(leaves[len - 1]);
+    if level.is_empty() {
+        return Ok(TxMerkleNode::all_zeros());
     }
 
-    let merkle_tree = MerkleTree::<Hash256>::from_leaves(leaves.as_slice());
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| {
+                let right = pair.get(1).unwrap_or(&pair[0]);
 
-    let root = match merkle_tree.root() {
-        Some(r) => r,
-        None => return Ok(TxMerkleNode::all_zeros()),
-    };
+                Hash256::hash(&[pair[0], *right].concat())
+            })
+            .collect();
+    }
+    let root = level[0];
 
     let hash = match Hash::from_slice(root.as_slice()) {
         Ok(h) => h,
@@ -241,6 +242,23 @@ mod tests {
         );
     }
 
+    #[test]
+    fn calculate_merkle_root_matches_bitcoin() {
+        for count in 1..10 {
+            let txids: Vec<Txid> = (0..count)
+                .map(|i| <Txid as bitcoin::hashes::Hash>::from_byte_array([i; 32]))
+                .collect();
+
+            let merkle_root = super::calculate_merkle_root(txids.clone()).unwrap();
+
+            assert_eq!(
+                Some(merkle_root),
+                bitcoin::merkle_tree::calculate_root(txids.into_iter())
+                    .map(|root| TxMerkleNode::from_raw_hash(root.to_raw_hash()))
+            );
+        }
+    }
+
     #[test]
     fn encode_decode_txid() {
         let txid = Txid::from_raw_hash(